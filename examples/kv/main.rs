@@ -0,0 +1,46 @@
+use raft::{config::Config, error::RaftError, node::RaftNode};
+use tokio::time::{sleep, Duration};
+
+mod store;
+
+use store::{KvCommand, KvStore};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // A single node cluster elects itself leader after its first election timeout
+    let mut node = RaftNode::builder(0, "[::1]:50054".to_string(), vec![])
+        .state_machine(KvStore::default())
+        .start()
+        .await?;
+    let proposer = node.clone();
+    let config = Config::new(1000, 2000, 300)?;
+    tokio::spawn(async move {
+        let _ = node.run(config).await;
+    });
+
+    let commands = vec![
+        KvCommand::Set("language".to_string(), "rust".to_string()),
+        KvCommand::Set("consensus".to_string(), "raft".to_string()),
+        KvCommand::Delete("language".to_string()),
+    ];
+    for command in commands {
+        loop {
+            match proposer.propose(command.clone()).await {
+                Ok(index) => {
+                    println!("Committed at index {}", index);
+                    break;
+                }
+                Err(RaftError::NotLeader { .. }) => sleep(Duration::from_millis(500)).await,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    // Entries are applied in the background after they commit, the read waits for them
+    let entries = proposer
+        .linearizable_read(|store| store.entries.clone())
+        .await?;
+    println!("{:?}", entries);
+
+    Ok(())
+}
@@ -0,0 +1,99 @@
+//! Key-value store replicated by raft, also run on a `TestCluster` by the tests.
+
+use std::{collections::HashMap, convert::TryInto};
+
+use raft::{error::DecodeError, raft::RaftData, state_machine::StateMachine};
+
+/// Commands of a replicated key-value store.
+#[derive(Clone)]
+pub enum KvCommand {
+    Set(String, String),
+    Delete(String),
+}
+
+impl RaftData for KvCommand {
+    fn as_bytes(&self) -> Vec<u8> {
+        // Tag byte followed by length prefixed key and value
+        let (tag, key, value) = match self {
+            KvCommand::Set(key, value) => (0, key, value.as_str()),
+            KvCommand::Delete(key) => (1, key, ""),
+        };
+        let mut bytes = vec![tag];
+        bytes.extend_from_slice(&(key.len() as u64).to_be_bytes());
+        bytes.extend_from_slice(key.as_bytes());
+        bytes.extend_from_slice(value.as_bytes());
+        bytes
+    }
+
+    fn try_from_bytes(bytes: Vec<u8>) -> Result<Self, DecodeError> {
+        let truncated = || DecodeError("truncated command".to_string());
+        let len = bytes.get(1..9).ok_or_else(truncated)?;
+        let len = u64::from_be_bytes(len.try_into().unwrap()) as usize;
+        let key = bytes
+            .get(9..9usize.saturating_add(len))
+            .ok_or_else(truncated)?;
+        let key = String::from_utf8_lossy(key).into_owned();
+        match bytes[0] {
+            0 => Ok(KvCommand::Set(
+                key,
+                String::from_utf8_lossy(&bytes[9 + len..]).into_owned(),
+            )),
+            1 => Ok(KvCommand::Delete(key)),
+            tag => Err(DecodeError(format!("unknown tag {}", tag))),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct KvStore {
+    pub entries: HashMap<String, String>,
+}
+
+impl StateMachine for KvStore {
+    type Command = KvCommand;
+
+    /// Outputs the value replaced or deleted, empty if there was none.
+    fn apply(&mut self, _: u64, command: KvCommand) -> Vec<u8> {
+        let previous = match command {
+            KvCommand::Set(key, value) => self.entries.insert(key, value),
+            KvCommand::Delete(key) => self.entries.remove(&key),
+        };
+        previous.unwrap_or_default().into_bytes()
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        // Length prefixed keys and values, sorted by key so that every node's snapshot is the same
+        let mut keys: Vec<_> = self.entries.keys().collect();
+        keys.sort();
+        let mut bytes = vec![];
+        for key in keys {
+            for field in [key, &self.entries[key]].iter() {
+                bytes.extend_from_slice(&(field.len() as u64).to_be_bytes());
+                bytes.extend_from_slice(field.as_bytes());
+            }
+        }
+        bytes
+    }
+
+    fn restore(&mut self, mut snapshot: &[u8]) -> Result<(), DecodeError> {
+        let truncated = || DecodeError("truncated snapshot".to_string());
+        let mut fields = vec![];
+        while !snapshot.is_empty() {
+            let len = snapshot.get(..8).ok_or_else(truncated)?;
+            let len = u64::from_be_bytes(len.try_into().unwrap()) as usize;
+            let field = snapshot[8..].get(..len).ok_or_else(truncated)?;
+            fields.push(String::from_utf8_lossy(field).into_owned());
+            snapshot = &snapshot[8 + len..];
+        }
+        if fields.len() % 2 != 0 {
+            return Err(truncated());
+        }
+
+        let mut fields = fields.into_iter();
+        self.entries.clear();
+        while let (Some(key), Some(value)) = (fields.next(), fields.next()) {
+            self.entries.insert(key, value);
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,7 @@
 use std::{error::Error, sync::Arc};
 use tokio::sync::Mutex;
 
-use raft::scheduler::{RaftCommand, StateMachine};
+use raft::state_machine::{RaftCommand, RaftStateMachine};
 
 #[derive(Debug, Copy, Clone)]
 pub enum ExecTask {
@@ -52,12 +52,13 @@ impl ExecUnit {
                             task: ExecTask::Render,
                             data: vec![(x >> 8) as u8, x as u8, y as u8],
                         };
-                        // queue.push(exec_unit.as_bytes());
+                        let _bytes = exec_unit.as_bytes();
+                        // queue.push(_bytes);
                     }
                 }
             }
             ExecTask::Render => {
-                let (i, j) = (
+                let (_i, _j) = (
                     (self.data[0] as u16) << 8 | self.data[1] as u16,
                     self.data[2] as u16,
                 );
@@ -70,23 +71,25 @@ impl ExecUnit {
 }
 
 struct Parallel {
-    s: Arc<Mutex<StateMachine>>,
+    s: Arc<Mutex<RaftStateMachine>>,
 }
 
 impl Parallel {
     pub fn new() -> Self {
         Self {
-            s: Arc::new(Mutex::new(StateMachine::new())),
+            s: Arc::new(Mutex::new(RaftStateMachine::new())),
         }
     }
 
     pub async fn run(&self) {
         let cmd = RaftCommand::from_bytes(vec![0, 1, 2, 3, 4]);
+        let unit = ExecUnit::from_bytes(cmd.data.clone());
         print!("{:#?}", self.s.lock().await.run_cmd(cmd));
+        unit.execute().await.unwrap();
     }
 }
 
 #[tokio::main]
 async fn main() {
-    Parallel::new().run(Config::new(0, 10, 15)).await;
+    Parallel::new().run().await;
 }
@@ -0,0 +1,38 @@
+use raft::{
+    config::Config,
+    error::RaftError,
+    node::RaftNode,
+    state_machine::{RaftCommand, RaftTask},
+};
+use tokio::time::{sleep, Duration};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // A single node cluster elects itself leader after its first election timeout
+    let mut node = RaftNode::start(0, "[::1]:50053".to_string(), vec![]).await?;
+    let proposer = node.clone();
+    let config = Config::new(1000, 2000, 300)?;
+    tokio::spawn(async move {
+        let _ = node.run(config).await;
+    });
+
+    for data in ["alpha", "beta", "gamma"].iter() {
+        loop {
+            let command = RaftCommand {
+                task: RaftTask::Occupy,
+                node: 1,
+                data: data.as_bytes().to_vec(),
+            };
+            match proposer.propose(command).await {
+                Ok(index) => {
+                    println!("{} committed at index {}", data, index);
+                    break;
+                }
+                Err(RaftError::NotLeader { .. }) => sleep(Duration::from_millis(500)).await,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use raft::raft_proto::{raft_admin_client::RaftAdminClient, Null, TransferRequest};
+use std::env;
+
+const USAGE: &str = "usage: raftctl <addr> status | members | transfer <target>";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let addr = args.first().ok_or(USAGE)?;
+    let mut client = RaftAdminClient::connect(format!("http://{}", addr)).await?;
+
+    match args.get(1).map(String::as_str) {
+        Some("status") => {
+            let status = client.cluster_status(Null {}).await?.into_inner();
+            println!("id            {}", status.id);
+            println!("role          {}", status.role);
+            println!("term          {}", status.current_term);
+            println!("leader        {}", status.leader);
+            println!("commit index  {}", status.commit_index);
+            println!("last applied  {}", status.last_applied);
+            println!("log length    {}", status.log_length);
+            println!("snapshot      {}", status.snapshot_index);
+            for peer in status.peers {
+                println!(
+                    "peer {} matched upto {}, {} behind",
+                    peer.addr, peer.match_index, peer.lag
+                );
+            }
+        }
+        Some("members") => {
+            let members = client.list_members(Null {}).await?.into_inner();
+            for member in members.members {
+                println!("{} voter", member);
+            }
+            for learner in members.learners {
+                println!("{} learner", learner);
+            }
+        }
+        Some("transfer") => {
+            let target = args.get(2).ok_or(USAGE)?.clone();
+            client
+                .transfer_leadership(TransferRequest {
+                    target: target.clone(),
+                })
+                .await?;
+            println!("{} leads now", target);
+        }
+        _ => return Err(USAGE.into()),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use tonic::{Request, Response, Status};
+
+use crate::{
+    error::RaftError,
+    node::RaftNode,
+    raft_proto::{
+        raft_admin_server::RaftAdmin, ClusterStatusReply, MembersReply, Null, PeerStatus,
+        TransferRequest,
+    },
+    state_machine::StateMachine,
+};
+
+/// Serves operators, any node tells what it knows of the cluster, the leader also how far each
+/// node lags behind it.
+#[tonic::async_trait]
+impl<S: StateMachine> RaftAdmin for RaftNode<S> {
+    /// Role, term and progress of this node, along with replication to each other node if it
+    /// leads. Leader is empty if unknown.
+    async fn cluster_status(
+        &self,
+        _: Request<Null>,
+    ) -> Result<Response<ClusterStatusReply>, Status> {
+        let metrics = self.metrics().await;
+        let leader = self.leader().await.unwrap_or_default();
+        let mut peers: Vec<PeerStatus> = metrics
+            .peers
+            .into_iter()
+            .map(|(addr, peer)| PeerStatus {
+                addr,
+                match_index: peer.match_index,
+                lag: peer.lag,
+            })
+            .collect();
+        peers.sort_by(|a, b| a.addr.cmp(&b.addr));
+
+        Ok(Response::new(ClusterStatusReply {
+            id: metrics.id,
+            role: format!("{:?}", metrics.role),
+            current_term: metrics.current_term,
+            leader,
+            commit_index: metrics.commit_index,
+            last_applied: metrics.last_applied,
+            log_length: metrics.log_length,
+            snapshot_index: metrics.snapshot_index,
+            peers,
+        }))
+    }
+
+    /// Voting members and learners of the cluster as this node knows of them.
+    async fn list_members(&self, _: Request<Null>) -> Result<Response<MembersReply>, Status> {
+        let membership = self.membership().await;
+        Ok(Response::new(MembersReply {
+            members: membership.members,
+            learners: membership.learners,
+        }))
+    }
+
+    /// Hands leadership over to target, only the leader can.
+    async fn transfer_leadership(
+        &self,
+        request: Request<TransferRequest>,
+    ) -> Result<Response<Null>, Status> {
+        let target = request.into_inner().target;
+        match self.transfer_leadership(target).await {
+            Ok(()) => Ok(Response::new(Null {})),
+            Err(e @ RaftError::NotLeader { .. }) => Err(Status::failed_precondition(e.to_string())),
+            Err(e @ RaftError::NotVoter) => Err(Status::invalid_argument(e.to_string())),
+            Err(e @ RaftError::Timeout) => Err(Status::deadline_exceeded(e.to_string())),
+            Err(e) => Err(Status::unavailable(e.to_string())),
+        }
+    }
+}
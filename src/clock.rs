@@ -0,0 +1,75 @@
+use std::sync::Arc;
+use tokio::{
+    sync::watch,
+    time::{Duration, Instant},
+};
+
+/// Source of time for a node, timing its elections, heartbeats and leases. Nodes use the tokio
+/// clock by default, tests can drive them by hand with a `ManualClock` instead.
+#[tonic::async_trait]
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    /// Resolves once duration has passed on this clock.
+    async fn sleep(&self, duration: Duration);
+
+    /// Time passed on this clock since earlier, zero if earlier is yet to come.
+    fn elapsed(&self, earlier: Instant) -> Duration {
+        self.now().saturating_duration_since(earlier)
+    }
+}
+
+/// Tells time with tokio, which follows the wall clock unless paused in a test.
+#[derive(Debug, Default, Clone)]
+pub struct TokioClock;
+
+#[tonic::async_trait]
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+}
+
+/// Clock that stands still until advanced, waking whoever sleeps past the time it is moved to.
+/// Clones share the same time, so a test can keep one to advance the clock of a node.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<watch::Sender<Instant>>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        let (now, _) = watch::channel(Instant::now());
+        Self { now: Arc::new(now) }
+    }
+
+    /// Moves the clock forward by duration.
+    pub fn advance(&self, duration: Duration) {
+        self.now.send_modify(|now| *now += duration);
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[tonic::async_trait]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.borrow()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        let mut now = self.now.subscribe();
+        let deadline = *now.borrow_and_update() + duration;
+        while *now.borrow_and_update() < deadline {
+            // Sender lives as long as self, so changes keep coming
+            let _ = now.changed().await;
+        }
+    }
+}
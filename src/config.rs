@@ -1,27 +1,500 @@
 use rand::Rng;
+#[cfg(feature = "toml")]
 use serde::{Deserialize, Serialize};
+#[cfg(any(feature = "toml", feature = "tls"))]
+use std::fs;
+#[cfg(feature = "tls")]
+use std::path::PathBuf;
+#[cfg(feature = "toml")]
+use std::{collections::HashMap, env, path::Path};
+use std::{
+    collections::HashSet,
+    net::{Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+#[cfg(feature = "tls")]
+use tonic::transport::{Certificate, ClientTlsConfig, Identity, ServerTlsConfig};
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::error::RaftError;
+
+/// Prefix of environment variables read by `Config::from_env`.
+#[cfg(feature = "toml")]
+const ENV_PREFIX: &str = "RAFT_";
+
+/// Settings of a node, which can be loaded from a TOML file or the environment. Keys left out
+/// take their default values, unknown keys are rejected.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "toml", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "toml", serde(default, deny_unknown_fields))]
 pub struct Config {
+    /// Id of the node, unique within the cluster
+    pub id: u64,
+    /// Address on which the node serves RPCs
+    pub addr: String,
+    /// Addresses of all nodes the cluster starts out with, including this one
+    pub nodes: Vec<String>,
+    /// Whether a node that is the only member of its cluster and has an empty log leads it right
+    /// away, seeding a new cluster that others then join before they run
+    pub bootstrap: bool,
+    /// Lower bound of election timeout, in milliseconds
     pub election_timeout_min: u64,
+    /// Upper bound of election timeout, in milliseconds
     pub election_timeout_max: u64,
+    /// Interval between heartbeats sent by a leader, in milliseconds, shorter than the election
+    /// timeout so that followers hear from the leader before they campaign
     pub heartbeat_interval: u64,
+    /// Number of entries applied since the latest snapshot after which a new one is taken
+    /// and the log compacted, 0 disables automatic snapshots
+    pub snapshot_threshold: u64,
+    /// Maximum number of bytes of snapshot sent per InstallSnapshot to followers that lag behind
+    pub snapshot_chunk_size: usize,
+    /// Maximum number of entries sent per AppendEntries, followers far behind catch up over as
+    /// many rounds as it takes
+    pub max_append_entries: usize,
+    /// Most entries a learner may lag behind the leader's log for it to be promoted to voter
+    pub promotion_lag: u64,
+    /// Whether the leader promotes learners to voters on its own, as soon as they caught up
+    pub auto_promote: bool,
+    /// Whether candidates first check that a majority would vote for them before starting an
+    /// election, keeping nodes rejoining after a partition from deposing a live leader
+    pub pre_vote: bool,
+    /// Whether the leader serves `read_local` from a lease, which every heartbeat round a majority
+    /// acknowledges extends. Assumes clocks of the nodes drift apart by less than the margin the
+    /// lease leaves before the shortest election timeout
+    pub lease_reads: bool,
+    /// Fraction of the shortest election timeout a lease lasts for, below 1 to allow for drift
+    pub lease_factor: f64,
+    /// Multiple of the shortest election timeout after which a node that leads no more and heard
+    /// from no leader reports `NOT_SERVING` to gRPC health checks, at least 1
+    pub health_factor: f64,
+    /// Whether the node serves the `RaftAdmin` gRPC service next to the RPCs between nodes, for
+    /// operators to query the cluster and transfer leadership
+    pub admin: bool,
+    /// Whether followers relay proposals to the leader they know of and return its result, rather
+    /// than refusing them with `NotLeader`
+    pub forward_to_leader: bool,
+    /// Longest time the leader holds a proposal back for more to replicate along with it in one
+    /// AppendEntries, in milliseconds. 0 replicates every proposal as soon as it is made
+    pub batch_linger: u64,
+    /// Number of proposals held back at which the leader replicates them without lingering any
+    /// longer
+    pub max_batch_entries: usize,
+    /// Longest time a node waits for another to reply to an RPC, in milliseconds, after which the
+    /// RPC counts as failed. Keeps a node that stopped responding from stalling elections and
+    /// replication, best kept below the shortest election timeout
+    pub rpc_timeout: u64,
+    /// Longest time a node waits for a connection to another node to be established, in
+    /// milliseconds, after which dialing it fails. Bounds RPCs that no RPC timeout applies to,
+    /// such as joining a cluster or forwarding proposals
+    pub connect_timeout: u64,
+    /// Time after which a node that an RPC failed to reach is first sent another, in
+    /// milliseconds. The wait doubles with each further failure, with some jitter
+    pub retry_backoff_min: u64,
+    /// Longest time to wait before sending another RPC to a node that keeps failing, in
+    /// milliseconds
+    pub retry_backoff_max: u64,
+    /// Certificates with which RPCs between nodes are secured, which are sent in plaintext if None
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsConfig>,
+}
+
+/// Paths to the PEM files with which a node secures its RPCs. Nodes present their certificate to
+/// one another and only accept peers whose certificate the CA signed.
+#[cfg(feature = "tls")]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "toml", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "toml", serde(deny_unknown_fields))]
+pub struct TlsConfig {
+    /// Certificate of this node
+    pub cert: PathBuf,
+    /// Private key of the node's certificate
+    pub key: PathBuf,
+    /// Certificate of the authority that signs the certificates of all nodes
+    pub ca: PathBuf,
+    /// Name certificates of nodes are issued for, checked in place of the addresses nodes are
+    /// dialed at
+    pub domain: String,
+    /// Whether RPCs are refused with `Status::unauthenticated` unless the peer's certificate
+    /// names the node they come from by its address, as common name or DNS name. Requests that
+    /// don't carry the sender's address must come from a voting member. Peers whose certificate
+    /// the CA didn't sign never get past the handshake either way
+    #[cfg_attr(feature = "toml", serde(default))]
+    pub verify_peers: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        // Heartbeat thrice within the shortest election timeout
+        Self {
+            id: 0,
+            addr: String::new(),
+            nodes: vec![],
+            bootstrap: false,
+            election_timeout_min: 150,
+            election_timeout_max: 300,
+            heartbeat_interval: 50,
+            snapshot_threshold: 10_000,
+            snapshot_chunk_size: 1 << 20,
+            max_append_entries: 1000,
+            promotion_lag: 100,
+            auto_promote: true,
+            pre_vote: true,
+            lease_reads: false,
+            lease_factor: 0.9,
+            health_factor: 3.0,
+            admin: true,
+            forward_to_leader: false,
+            batch_linger: 0,
+            max_batch_entries: 64,
+            rpc_timeout: 100,
+            connect_timeout: 1000,
+            retry_backoff_min: 50,
+            retry_backoff_max: 2000,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
 }
 
 impl Config {
-    pub fn new_rand_election_timeout(&self) -> u64 {
-        rand::thread_rng().gen_range(self.election_timeout_min..self.election_timeout_max)
+    pub fn heartbeat_interval(&self) -> Duration {
+        Duration::from_millis(self.heartbeat_interval)
+    }
+
+    /// Time a lease lasts for after the heartbeats acknowledged by a majority were sent, None if
+    /// lease reads are disabled.
+    pub fn lease_duration(&self) -> Option<Duration> {
+        if !self.lease_reads {
+            return None;
+        }
+        let timeout = self.election_timeout_min();
+        Some(timeout.mul_f64(self.lease_factor.clamp(0.0, 1.0)))
+    }
+
+    /// Time without contact to a leader after which a node reports it isn't serving.
+    pub fn health_timeout(&self) -> Duration {
+        self.election_timeout_min()
+            .mul_f64(self.health_factor.max(1.0))
+    }
+
+    pub fn election_timeout_min(&self) -> Duration {
+        Duration::from_millis(self.election_timeout_min)
+    }
+
+    pub fn election_timeout_max(&self) -> Duration {
+        Duration::from_millis(self.election_timeout_max)
     }
 
+    pub fn batch_linger(&self) -> Duration {
+        Duration::from_millis(self.batch_linger)
+    }
+
+    pub fn rpc_timeout(&self) -> Duration {
+        Duration::from_millis(self.rpc_timeout)
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_millis(self.connect_timeout)
+    }
+
+    pub fn retry_backoff_min(&self) -> Duration {
+        Duration::from_millis(self.retry_backoff_min)
+    }
+
+    pub fn retry_backoff_max(&self) -> Duration {
+        Duration::from_millis(self.retry_backoff_max)
+    }
+
+    /// Election timeout picked at random within its bounds, so that nodes rarely campaign at once.
+    pub fn new_rand_election_timeout(&self) -> Duration {
+        let timeout =
+            rand::thread_rng().gen_range(self.election_timeout_min..=self.election_timeout_max);
+        Duration::from_millis(timeout)
+    }
+
+    /// Checks that heartbeats are sent more often than the shortest election timeout, that RPCs
+    /// get time to complete, and that the bounds of the election timeout and of retry backoff are
+    /// in order.
+    pub fn validate(&self) -> Result<(), RaftError> {
+        if self.heartbeat_interval == 0 {
+            Err(RaftError::InvalidConfig(
+                "heartbeat interval must be positive".to_string(),
+            ))
+        } else if self.heartbeat_interval >= self.election_timeout_min {
+            Err(RaftError::InvalidConfig(
+                "heartbeat interval must be shorter than the election timeout".to_string(),
+            ))
+        } else if self.election_timeout_min >= self.election_timeout_max {
+            // Nodes that time out alike would keep campaigning at once, splitting the vote
+            Err(RaftError::InvalidConfig(
+                "election timeout minimum must be below its maximum".to_string(),
+            ))
+        } else if self.health_factor.is_nan() || self.health_factor < 1.0 {
+            Err(RaftError::InvalidConfig(
+                "health factor must be at least 1".to_string(),
+            ))
+        } else if self.max_append_entries == 0 {
+            Err(RaftError::InvalidConfig(
+                "max append entries must be positive".to_string(),
+            ))
+        } else if self.max_batch_entries == 0 {
+            Err(RaftError::InvalidConfig(
+                "max batch entries must be positive".to_string(),
+            ))
+        } else if self.rpc_timeout == 0 {
+            Err(RaftError::InvalidConfig(
+                "RPC timeout must be positive".to_string(),
+            ))
+        } else if self.connect_timeout == 0 {
+            Err(RaftError::InvalidConfig(
+                "connect timeout must be positive".to_string(),
+            ))
+        } else if self.retry_backoff_min > self.retry_backoff_max {
+            Err(RaftError::InvalidConfig(
+                "retry backoff minimum must not exceed its maximum".to_string(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks that the addresses of the node and of the cluster are valid, see `validate_addr`,
+    /// and distinct, with the node one of the nodes of the cluster.
+    pub fn validate_cluster(&self) -> Result<(), RaftError> {
+        validate_addr(&self.addr)?;
+        for node in &self.nodes {
+            validate_addr(node)?;
+        }
+        if self.nodes.is_empty() {
+            return Err(RaftError::InvalidConfig(
+                "nodes must list at least this node".to_string(),
+            ));
+        } else if !self.nodes.contains(&self.addr) {
+            return Err(RaftError::InvalidConfig(format!(
+                "nodes must include this node's address {}",
+                self.addr
+            )));
+        }
+        let mut seen = HashSet::new();
+        for node in &self.nodes {
+            if !seen.insert(node) {
+                return Err(RaftError::InvalidConfig(format!(
+                    "node {} listed more than once",
+                    node
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Loads config of a node from the TOML file at path, with keys named after the fields.
+    #[cfg(feature = "toml")]
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, RaftError> {
+        let contents = fs::read_to_string(path)?;
+        let config: Self =
+            toml::from_str(&contents).map_err(|e| RaftError::InvalidConfig(e.to_string()))?;
+        config.validate()?;
+        config.validate_cluster()?;
+        Ok(config)
+    }
+
+    /// Loads config of a node from environment variables named after the fields in upper case,
+    /// prefixed with `RAFT_`, such as `RAFT_ADDR`. `RAFT_NODES` holds comma separated addresses,
+    /// fields of `tls` are prefixed with `RAFT_TLS_`, such as `RAFT_TLS_CERT`. Values are read as
+    /// the type of their field, failing with `InvalidConfig` if they aren't one. The prefix is
+    /// reserved, any other variable starting with it fails with `InvalidConfig` naming it.
+    #[cfg(feature = "toml")]
+    pub fn from_env() -> Result<Self, RaftError> {
+        let vars = env_vars();
+        let mut table = toml::value::Table::new();
+        for (var, value) in env::vars() {
+            let key = match var.strip_prefix(ENV_PREFIX) {
+                Some(key) => key.to_lowercase(),
+                None => continue,
+            };
+            let value = match vars.get(&var) {
+                Some(field) => env_value(&var, value, field)?,
+                None => {
+                    return Err(RaftError::InvalidConfig(format!(
+                        "unknown environment variable {}",
+                        var
+                    )))
+                }
+            };
+            if let Some(field) = key.strip_prefix("tls_") {
+                let tls = table
+                    .entry("tls")
+                    .or_insert_with(|| toml::Value::Table(toml::value::Table::new()));
+                if let toml::Value::Table(tls) = tls {
+                    tls.insert(field.to_string(), value);
+                }
+            } else {
+                table.insert(key, value);
+            }
+        }
+
+        let config: Self = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e: toml::de::Error| RaftError::InvalidConfig(e.to_string()))?;
+        config.validate()?;
+        config.validate_cluster()?;
+        Ok(config)
+    }
+
+    /// Config with election timeouts between min and max and heartbeats at interval, all in
+    /// milliseconds, fails with `InvalidConfig` unless heartbeats are more frequent than elections.
     pub fn new(
         election_timeout_min: u64,
         election_timeout_max: u64,
         heartbeat_interval: u64,
-    ) -> Self {
-        Self {
+    ) -> Result<Self, RaftError> {
+        let config = Self {
             election_timeout_min,
             election_timeout_max,
             heartbeat_interval,
+            ..Self::default()
+        };
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[cfg(feature = "tls")]
+impl TlsConfig {
+    /// Settings with which the server presents the node's certificate and requires clients to
+    /// present one signed by the CA. Fails if the files can't be read.
+    pub fn server_config(&self) -> Result<ServerTlsConfig, RaftError> {
+        Ok(ServerTlsConfig::new()
+            .identity(self.identity()?)
+            .client_ca_root(self.ca()?))
+    }
+
+    /// Settings with which nodes are dialed, presenting the node's certificate and trusting only
+    /// servers whose certificate the CA signed for the domain. Fails if the files can't be read.
+    pub fn client_config(&self) -> Result<ClientTlsConfig, RaftError> {
+        Ok(ClientTlsConfig::new()
+            .domain_name(self.domain.clone())
+            .ca_certificate(self.ca()?)
+            .identity(self.identity()?))
+    }
+
+    fn identity(&self) -> Result<Identity, RaftError> {
+        Ok(Identity::from_pem(
+            fs::read(&self.cert)?,
+            fs::read(&self.key)?,
+        ))
+    }
+
+    fn ca(&self) -> Result<Certificate, RaftError> {
+        Ok(Certificate::from_pem(fs::read(&self.ca)?))
+    }
+}
+
+/// Checks that addr is a host and port, the host being a hostname, which is resolved whenever the
+/// node is dialed, or an IP address, in brackets if IPv6 such as `[::1]:50051`.
+pub fn validate_addr(addr: &str) -> Result<(), RaftError> {
+    let invalid = |reason| {
+        Err(RaftError::InvalidAddr {
+            addr: addr.to_string(),
+            reason,
+        })
+    };
+    let (host, port) = match addr.rsplit_once(':') {
+        Some(parts) => parts,
+        None => return invalid("expected host:port"),
+    };
+    if port.parse::<u16>().is_err() {
+        return invalid("port must be a number upto 65535");
+    }
+    if let Some(ip) = host.strip_prefix('[').and_then(|ip| ip.strip_suffix(']')) {
+        return match ip.parse::<Ipv6Addr>() {
+            Ok(_) => Ok(()),
+            Err(_) => invalid("brackets must enclose an IPv6 address"),
+        };
+    }
+    if host.is_empty() {
+        invalid("host is missing")
+    } else if host.contains(':') {
+        invalid("IPv6 addresses must be enclosed in brackets")
+    } else if host.parse::<Ipv4Addr>().is_err() && !is_hostname(host) {
+        invalid("host must be a hostname or an IP address")
+    } else {
+        Ok(())
+    }
+}
+
+/// Whether host is made of dot separated labels of letters, digits, hyphens and underscores, which
+/// don't start or end with a hyphen. A trailing dot marks it fully qualified.
+fn is_hostname(host: &str) -> bool {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    host.len() <= 253
+        && host.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+}
+
+/// Names of the environment variables `Config::from_env` reads, one per field of the config and of
+/// its `tls`, each with the default value of its field.
+#[cfg(feature = "toml")]
+fn env_vars() -> HashMap<String, toml::Value> {
+    #[allow(unused_mut)]
+    let mut config = Config::default();
+    #[cfg(feature = "tls")]
+    {
+        config.tls = Some(TlsConfig {
+            cert: PathBuf::new(),
+            key: PathBuf::new(),
+            ca: PathBuf::new(),
+            domain: String::new(),
+            verify_peers: false,
+        });
+    }
+    let var = |key: &str| format!("{}{}", ENV_PREFIX, key.to_uppercase());
+    let mut vars = HashMap::new();
+    if let Ok(toml::Value::Table(table)) = toml::Value::try_from(config) {
+        for (key, value) in table {
+            if let toml::Value::Table(fields) = value {
+                for (field, value) in fields {
+                    vars.insert(var(&format!("{}_{}", key, field)), value);
+                }
+            } else {
+                vars.insert(var(&key), value);
+            }
         }
     }
+    vars
+}
+
+/// Value of environment variable var as the type of field, failing with `InvalidConfig` if it
+/// isn't one. Arrays are read from comma separated strings.
+#[cfg(feature = "toml")]
+fn env_value(var: &str, value: String, field: &toml::Value) -> Result<toml::Value, RaftError> {
+    let invalid =
+        |kind: &str| RaftError::InvalidConfig(format!("{} must be {}, got {:?}", var, kind, value));
+    Ok(match field {
+        toml::Value::Integer(_) => {
+            toml::Value::Integer(value.parse().map_err(|_| invalid("an integer"))?)
+        }
+        toml::Value::Float(_) => {
+            toml::Value::Float(value.parse().map_err(|_| invalid("a number"))?)
+        }
+        toml::Value::Boolean(_) => {
+            toml::Value::Boolean(value.parse().map_err(|_| invalid("true or false"))?)
+        }
+        toml::Value::Array(_) => toml::Value::Array(
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| toml::Value::String(item.to_string()))
+                .collect(),
+        ),
+        _ => toml::Value::String(value),
+    })
 }
@@ -0,0 +1,125 @@
+use std::{error::Error, fmt, io};
+use tonic::Status;
+
+/// Errors returned by `RaftNode::propose`.
+pub type ProposeError = RaftError;
+
+/// Errors returned by the raft node, distinguishing requests to be redirected to the leader or
+/// retried from failures to reach other nodes or persist state.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum RaftError {
+    /// Node isn't the leader and can't serve the request, carries address of the leader if known
+    NotLeader { leader: Option<String> },
+    /// Entry was removed from log before it could be committed
+    Dropped,
+    /// Another membership change is still waiting to be committed
+    MembershipChanging,
+    /// Node to be promoted is not a learner of the cluster
+    NotLearner,
+    /// Learner's log is too far behind the leader's for it to be promoted
+    LearnerBehind,
+    /// Node is not a voting member of the cluster
+    NotVoter,
+    /// Leadership is being transferred to another node, which will take requests instead
+    Transferring,
+    /// Operation didn't complete in time
+    Timeout,
+    /// Leader couldn't hear back from a majority of the cluster
+    NoQuorum,
+    /// Leader doesn't hold a lease to serve local reads, it expired or leases are disabled
+    LeaseExpired,
+    /// Node is shutting down and no longer takes requests
+    ShuttingDown,
+    /// Node at addr claimed an id that this node or another member already goes by
+    DuplicateId {
+        id: u64,
+        addr: String,
+        taken_by: String,
+    },
+    /// Address of a node isn't a host and port, for the reason given
+    InvalidAddr { addr: String, reason: &'static str },
+    /// Config was rejected, for the reason given
+    InvalidConfig(String),
+    /// Failed to reach another node, or it failed to serve an RPC
+    Transport(Box<Status>),
+    /// Entry at index of the persisted log doesn't match its checksum, or was cut short
+    LogCorruption { index: u64 },
+    /// Failed to persist state
+    Io(io::Error),
+}
+
+impl Error for RaftError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Transport(status) => Some(status.as_ref()),
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RaftError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotLeader { leader: Some(addr) } => write!(f, "Not leader, try {}", addr),
+            Self::NotLeader { leader: None } => write!(f, "Not leader, leader unknown"),
+            Self::Dropped => write!(f, "Entry dropped before commit"),
+            Self::MembershipChanging => write!(f, "Membership change already in progress"),
+            Self::NotLearner => write!(f, "Node is not a learner"),
+            Self::LearnerBehind => write!(f, "Learner hasn't caught up with the leader"),
+            Self::NotVoter => write!(f, "Node is not a voting member"),
+            Self::Transferring => write!(f, "Leadership transfer in progress"),
+            Self::Timeout => write!(f, "Timed out"),
+            Self::NoQuorum => write!(f, "Leader couldn't reach a majority"),
+            Self::LeaseExpired => write!(f, "Leader lease expired"),
+            Self::ShuttingDown => write!(f, "Node is shutting down"),
+            Self::DuplicateId { id, addr, taken_by } => write!(
+                f,
+                "Node id {} claimed by {} is already taken by {}",
+                id, addr, taken_by
+            ),
+            Self::InvalidAddr { addr, reason } => {
+                write!(f, "Invalid address {:?}, {}", addr, reason)
+            }
+            Self::InvalidConfig(reason) => write!(f, "Invalid config, {}", reason),
+            Self::Transport(status) => write!(f, "Transport error: {}", status.message()),
+            Self::LogCorruption { index } => write!(f, "Log entry {} is corrupt", index),
+            Self::Io(e) => write!(f, "IO error: {}", e),
+        }
+    }
+}
+
+/// Bytes of a committed entry that don't decode into a command, for the reason given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError(pub String);
+
+impl Error for DecodeError {}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Couldn't decode command: {}", self.0)
+    }
+}
+
+impl From<io::Error> for RaftError {
+    fn from(e: io::Error) -> Self {
+        // Log stores report corruption through IO errors, carrying the index of the entry
+        match e.get_ref().and_then(|e| e.downcast_ref::<RaftError>()) {
+            Some(Self::LogCorruption { index }) => Self::LogCorruption { index: *index },
+            _ => Self::Io(e),
+        }
+    }
+}
+
+impl From<Status> for RaftError {
+    fn from(status: Status) -> Self {
+        Self::Transport(Box::new(status))
+    }
+}
+
+impl From<tonic::transport::Error> for RaftError {
+    fn from(e: tonic::transport::Error) -> Self {
+        Self::Transport(Box::new(Status::unavailable(e.to_string())))
+    }
+}
@@ -1,11 +1,18 @@
 //! RaEx is a tool to help you build high performance compute clusters, with which you can run
 //! computational tasks that would otherwise be incredibly inefficient on a single system.
 
+pub mod admin;
+pub mod clock;
 pub mod config;
+pub mod error;
 pub mod node;
 pub mod raft;
 pub mod state_machine;
+pub mod storage;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod transport;
 
-mod raft_proto {
+pub mod raft_proto {
     tonic::include_proto!("raft");
 }
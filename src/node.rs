@@ -1,125 +1,1768 @@
-use rand::Rng;
-use std::{cmp::min, error::Error, sync::Arc};
+use std::{
+    cmp::min, convert::TryFrom, future::Future, io, net::SocketAddr, path::PathBuf, sync::Arc,
+};
 use tokio::{
-    sync::Mutex,
+    net::TcpListener,
+    sync::{broadcast, oneshot, watch, Mutex},
+    task::JoinHandle,
     time::{Duration, Instant},
 };
-use tonic::{transport::Server, Request, Response, Status};
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::{transport::Server, Code, Request, Response, Status};
+use tonic_health::{server::health_reporter, ServingStatus};
+use tracing::{debug, error, field, info, info_span, instrument, trace, Instrument, Span};
 
+#[cfg(feature = "tls")]
+use crate::config::TlsConfig;
 use crate::{
-    config::Config,
-    raft::RaftDetails,
+    clock::Clock,
+    config::{validate_addr, Config},
+    error::{ProposeError, RaftError},
+    raft::{
+        campaign, confirm_leadership, replicate, start_election, timeout_now, EntryKind, LogEntry,
+        NodeRole, RaftData, RaftDetails, RaftEvent, RaftMetrics, RaftStatus, Standing,
+    },
     raft_proto::{
+        self,
+        raft_admin_server::RaftAdminServer,
         raft_server::{Raft, RaftServer},
-        Byte, EntryReply, EntryRequest, Null, VoteReply, VoteRequest,
+        Byte, EntryReply, EntryRequest, Null, ProposeReply, SnapshotReply, SnapshotRequest,
+        TimeoutReply, TimeoutRequest, VoteReply, VoteRequest,
     },
+    state_machine::{RaftStateMachine, StateMachine},
+    storage::{LogStore, MemLog, MemStorage, Membership, Snapshot, SnapshotMeta, Storage},
+    transport::{GrpcTransport, RaftTransport},
 };
 
 /// Details necessary to construct a node for raft consensus.
-pub struct RaftNode {
+pub struct RaftNode<S: StateMachine = RaftStateMachine> {
     details: Arc<Mutex<RaftDetails>>,
+    state_machine: Arc<Mutex<S>>,
+    shutdown: Arc<Shutdown>,
+    /// Address the gRPC server is bound to, None when serving through another transport
+    local_addr: Option<SocketAddr>,
+    /// Whether peers must present a certificate naming the node they send RPCs from
+    #[cfg(feature = "tls")]
+    verify_peers: bool,
 }
 
-impl RaftNode {
-    /// Starts a raft node, consisting of server and client gRPC stubs.
-    pub async fn start(
-        id: u8,
-        local_addr: String,
-        mut nodes: Vec<String>,
-    ) -> Result<Self, Box<dyn Error>> {
+impl<S: StateMachine> Clone for RaftNode<S> {
+    fn clone(&self) -> Self {
+        Self {
+            details: self.details.clone(),
+            state_machine: self.state_machine.clone(),
+            shutdown: self.shutdown.clone(),
+            local_addr: self.local_addr,
+            #[cfg(feature = "tls")]
+            verify_peers: self.verify_peers,
+        }
+    }
+}
+
+/// Stops the server and background tasks of a node once it shuts down.
+struct Shutdown {
+    /// Set to true once the node shuts down
+    signal: watch::Sender<bool>,
+    stopped: watch::Receiver<bool>,
+    /// Server and background tasks, awaited on shutdown
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+/// Configures a raft node before starting it, allowing a custom state machine to be plugged in.
+pub struct RaftNodeBuilder<S: StateMachine> {
+    id: u64,
+    local_addr: String,
+    nodes: Vec<String>,
+    storage: Option<Box<dyn Storage>>,
+    log: Option<Box<dyn LogStore>>,
+    path: Option<PathBuf>,
+    transport: Option<Arc<dyn RaftTransport>>,
+    clock: Option<Arc<dyn Clock>>,
+    admin: bool,
+    connect_timeout: Duration,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConfig>,
+    state_machine: S,
+}
+
+impl<S: StateMachine> RaftNodeBuilder<S> {
+    /// Replaces the state machine to which committed entries are applied.
+    pub fn state_machine<T: StateMachine>(self, state_machine: T) -> RaftNodeBuilder<T> {
+        RaftNodeBuilder {
+            id: self.id,
+            local_addr: self.local_addr,
+            nodes: self.nodes,
+            storage: self.storage,
+            log: self.log,
+            path: self.path,
+            transport: self.transport,
+            clock: self.clock,
+            admin: self.admin,
+            connect_timeout: self.connect_timeout,
+            #[cfg(feature = "tls")]
+            tls: self.tls,
+            state_machine,
+        }
+    }
+
+    /// Recovers hard state from and persists it to storage, kept in memory by default.
+    pub fn storage<T: Storage + 'static>(mut self, storage: T) -> Self {
+        self.storage = Some(Box::new(storage));
+        self
+    }
+
+    /// Recovers log from and persists it to storage, kept in memory by default.
+    pub fn log_store<T: LogStore + 'static>(mut self, log: T) -> Self {
+        self.log = Some(Box::new(log));
+        self
+    }
+
+    /// Recovers hard state and log from and persists them to files in the directory at path,
+    /// replacing any configured storage.
+    pub fn path(mut self, path: PathBuf) -> Self {
+        self.path = Some(path);
+        self
+    }
+
+    /// Sends RPCs to other nodes through transport instead of gRPC, the node then doesn't serve
+    /// gRPC either and transport must deliver RPCs addressed to it, like `LocalTransport`.
+    pub fn transport<T: RaftTransport + 'static>(mut self, transport: T) -> Self {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// Times elections, heartbeats and leases by clock instead of tokio's, such as a
+    /// `ManualClock` that a test advances by hand.
+    pub fn clock<T: Clock + 'static>(mut self, clock: T) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Whether to serve the `RaftAdmin` service alongside the RPCs between nodes, as it does
+    /// unless disabled.
+    pub fn admin(mut self, admin: bool) -> Self {
+        self.admin = admin;
+        self
+    }
+
+    /// Fails to dial other nodes over gRPC unless connected within timeout, by default that of
+    /// `Config::default`.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Serves gRPC and dials other nodes over TLS with the certificates in tls, unless another
+    /// transport is configured.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Starts the raft node, consisting of server and client gRPC stubs. Fails if the address
+    /// can't be parsed, resolved or bound to, port 0 binds to any free port, see
+    /// `RaftNode::local_addr`. Addresses may name hosts, as checked by `validate_addr`.
+    pub async fn start(self) -> Result<RaftNode<S>, RaftError> {
+        let Self {
+            id,
+            local_addr,
+            mut nodes,
+            storage,
+            log,
+            path,
+            transport,
+            clock,
+            admin,
+            connect_timeout,
+            #[cfg(feature = "tls")]
+            tls,
+            mut state_machine,
+        } = self;
+        // Listener is bound upfront so that failures to bind surface here, not on the server task
+        let listener = match transport {
+            Some(_) => None,
+            None => {
+                validate_addr(&local_addr)?;
+                for node in &nodes {
+                    validate_addr(node)?;
+                }
+                Some(TcpListener::bind(local_addr.as_str()).await?)
+            }
+        };
+        // Certificates are read upfront as well, so that a node never serves without them
+        let builder = Server::builder();
+        #[cfg(feature = "tls")]
+        let verify_peers = listener.is_some() && tls.as_ref().is_some_and(|tls| tls.verify_peers);
+        #[cfg(feature = "tls")]
+        let (builder, transport) = match (tls, &listener) {
+            (Some(tls), Some(_)) => {
+                let builder = builder
+                    .tls_config(tls.server_config()?)
+                    .map_err(|e| RaftError::InvalidConfig(e.to_string()))?;
+                let transport = GrpcTransport::with_tls(tls.client_config()?);
+                let transport: Arc<dyn RaftTransport> =
+                    Arc::new(transport.with_connect_timeout(connect_timeout));
+                (builder, Some(transport))
+            }
+            _ => (builder, transport),
+        };
+        // Nodes serving gRPC dial one another over it, unless configured otherwise
+        let transport = transport.or_else(|| {
+            let transport = GrpcTransport::new().with_connect_timeout(connect_timeout);
+            listener
+                .as_ref()
+                .map(|_| Arc::new(transport) as Arc<dyn RaftTransport>)
+        });
+        // Nodes are told the port picked by the OS, when asked for any
+        let local_addr = match &listener {
+            Some(listener) if local_addr.ends_with(":0") => listener.local_addr()?.to_string(),
+            _ => local_addr,
+        };
         // Keep addr of all nodes but the current one in directory.
         nodes.retain(|x| *x != local_addr);
 
+        // Hard state is loaded before the server starts, so no RPC sees a stale term or vote
+        let mut details = match (path, storage, log) {
+            (Some(path), _, _) => RaftDetails::recover(id, nodes, path)?,
+            (None, None, None) => RaftDetails::new(id, nodes),
+            (None, storage, log) => RaftDetails::with_storage(
+                id,
+                nodes,
+                storage.unwrap_or_else(|| Box::new(MemStorage::new())),
+                log.unwrap_or_else(|| Box::new(MemLog::new())),
+            )?,
+        };
+        details.addr = local_addr;
+        if let Some(transport) = transport {
+            details.transport = transport;
+        }
+        if let Some(clock) = clock {
+            details.clock = clock;
+            details.reset_election_timer();
+            details.leader_contact = details.clock.now();
+        }
+        // Membership committed through the log replaces the nodes the node was started with
+        if let Some(membership) = details.membership_at(details.commit_index) {
+            details.apply_membership(membership);
+        }
+        // Entries replaced by the snapshot are already applied to the restored state machine
+        if details.snapshot.index > 0 {
+            state_machine
+                .restore(&details.snapshot.data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let index = details.snapshot.index;
+            details.set_last_applied(index);
+        }
+
         // Create shared state
-        let raft_details = Arc::new(Mutex::new(RaftDetails::new(id, nodes)));
+        let (signal, stopped) = watch::channel(false);
+        let raft = RaftNode {
+            details: Arc::new(Mutex::new(details)),
+            state_machine: Arc::new(Mutex::new(state_machine)),
+            shutdown: Arc::new(Shutdown {
+                signal,
+                stopped,
+                tasks: Mutex::new(vec![]),
+            }),
+            local_addr: match &listener {
+                Some(listener) => Some(listener.local_addr()?),
+                None => None,
+            },
+            #[cfg(feature = "tls")]
+            verify_peers,
+        };
+        // Entries committed after the snapshot are applied again in order, before the node serves
+        // anything, so that the state machine is as it was before the node restarted
+        raft.apply_committed().await;
 
-        // State that is handed over the the server stub on this node
-        let raft = Self {
-            details: raft_details.clone(),
+        let listener = match listener {
+            Some(listener) => listener,
+            None => return Ok(raft),
         };
+        // State that is handed over to the server stub on this node
+        let server = raft.clone();
+        let admin = admin.then(|| RaftAdminServer::new(raft.clone()));
 
-        // Server runs on a background thread and handles calls to the node
-        tokio::spawn(async move {
-            Server::builder()
-                .add_service(RaftServer::new(raft))
-                .serve(local_addr.parse().unwrap())
-                .await
-                .unwrap();
-        });
+        // Health service reports the node and its Raft service as serving while it leads or hears
+        // from a leader, and as not serving once it lost contact or began shutting down
+        let (mut reporter, health) = health_reporter();
+        let mut serving = raft.details.lock().await.serving.subscribe();
+        let mut stopped = raft.shutdown.stopped.clone();
+        raft.spawn(
+            async move {
+                loop {
+                    let status = match *serving.borrow_and_update() {
+                        true => ServingStatus::Serving,
+                        false => ServingStatus::NotServing,
+                    };
+                    for service in HEALTH_SERVICES {
+                        reporter.set_service_status(service, status).await;
+                    }
+                    tokio::select! {
+                        _ = serving.changed() => {}
+                        _ = stopped.changed() => break,
+                    }
+                }
+                for service in HEALTH_SERVICES {
+                    reporter
+                        .set_service_status(service, ServingStatus::NotServing)
+                        .await;
+                }
+            }
+            .instrument(info_span!("health", node_id = id)),
+        )
+        .await;
 
-        Ok(Self {
-            details: raft_details,
+        // Server runs on a background thread and handles calls to the node, until it shuts down
+        let mut stopped = raft.shutdown.stopped.clone();
+        let mut builder = builder;
+        raft.spawn(async move {
+            let served = builder
+                .add_service(RaftServer::new(server))
+                .add_service(health)
+                .add_optional_service(admin)
+                .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async move {
+                    let _ = stopped.changed().await;
+                })
+                .await;
+            if let Err(e) = served {
+                error!(error = %e, "server failed");
+            }
         })
+        .await;
+
+        Ok(raft)
+    }
+}
+
+impl RaftNode {
+    /// Configures a raft node with the default state machine, that can be replaced before start.
+    pub fn builder(
+        id: u64,
+        local_addr: String,
+        nodes: Vec<String>,
+    ) -> RaftNodeBuilder<RaftStateMachine> {
+        RaftNodeBuilder {
+            id,
+            local_addr,
+            nodes,
+            storage: None,
+            log: None,
+            path: None,
+            transport: None,
+            clock: None,
+            admin: true,
+            connect_timeout: Config::default().connect_timeout(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            state_machine: RaftStateMachine::new(),
+        }
+    }
+
+    /// Starts a raft node, consisting of server and client gRPC stubs.
+    pub async fn start(id: u64, local_addr: String, nodes: Vec<String>) -> Result<Self, RaftError> {
+        Self::builder(id, local_addr, nodes).start().await
+    }
+
+    /// Starts a raft node like `start` as the only member of a new cluster, which it leads as soon
+    /// as this resolves, so proposals commit without waiting for peers or an election timeout.
+    /// Further members are added through configuration changes, such as `add_node` or `join`.
+    /// Run the node as usual, there is no need for `Config::bootstrap`.
+    pub async fn bootstrap(id: u64, local_addr: String) -> Result<Self, RaftError> {
+        let node = Self::start(id, local_addr, vec![]).await?;
+        node.lead_alone().await?;
+        Ok(node)
+    }
+
+    /// Starts a raft node like `start`, with id, address and nodes of the cluster taken from
+    /// config, for instance as loaded by `Config::from_file`, as well as TLS certificates if any,
+    /// whether to serve the admin service and how long to wait for connections to other nodes.
+    pub async fn start_from(config: &Config) -> Result<Self, RaftError> {
+        config.validate_cluster()?;
+        let builder = Self::builder(config.id, config.addr.clone(), config.nodes.clone())
+            .admin(config.admin)
+            .connect_timeout(config.connect_timeout());
+        #[cfg(feature = "tls")]
+        let builder = match &config.tls {
+            Some(tls) => builder.tls(tls.clone()),
+            None => builder,
+        };
+        builder.start().await
+    }
+
+    /// Starts a raft node like `start`, but with hard state and log recovered from and persisted
+    /// to files in the directory at path.
+    pub async fn recover(
+        id: u64,
+        local_addr: String,
+        nodes: Vec<String>,
+        path: PathBuf,
+    ) -> Result<Self, RaftError> {
+        Self::builder(id, local_addr, nodes)
+            .path(path)
+            .start()
+            .await
+    }
+}
+
+impl<S: StateMachine> RaftNode<S> {
+    /// State machine to which committed entries are applied.
+    pub fn state_machine(&self) -> Arc<Mutex<S>> {
+        self.state_machine.clone()
+    }
+
+    /// Address the node serves gRPC on, with the port picked by the OS if it was started on port
+    /// 0. None if it was started with another transport.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// Address of the leader of the current term as last learnt by this node, None if it
+    /// doesn't know of one. Requests refused with `NotLeader` should be retried against it.
+    pub async fn leader(&self) -> Option<String> {
+        self.details.lock().await.leader_addr.clone()
+    }
+
+    /// Whether the node currently leads the cluster.
+    pub async fn is_leader(&self) -> bool {
+        self.details.lock().await.role == NodeRole::Leader
+    }
+
+    /// Current measurements of the node, copied out in one go so that the node is barely held up.
+    /// Enable the `metrics` feature for nodes to also record them through the `metrics` crate
+    /// every heartbeat interval.
+    pub async fn metrics(&self) -> RaftMetrics {
+        self.details.lock().await.metrics()
+    }
+
+    /// Current state of the node, copied out so that it can be polled without holding up the node.
+    pub async fn status(&self) -> RaftStatus {
+        self.details.lock().await.status()
+    }
+
+    /// Membership of the cluster currently in effect as this node knows of it, including itself
+    /// unless it was removed.
+    pub async fn membership(&self) -> Membership {
+        self.details.lock().await.membership()
+    }
+
+    /// Subscribes to changes of role, term or leader of the node, the receiver starts out with
+    /// the current status and is notified of every change from then on.
+    pub async fn subscribe(&self) -> watch::Receiver<RaftStatus> {
+        let details = self.details.lock().await;
+        let status = details.status();
+        // Brings the rest of the status up to date without notifying existing subscribers
+        details.events.send_if_modified(|current| {
+            *current = status;
+            false
+        });
+        details.events.subscribe()
+    }
+
+    /// Receives transitions of the node from now on. Events arrive in the order the node went
+    /// through them, each once the transition is complete, so that `is_leader` and `status` reflect
+    /// at least that transition by the time it is received. Only the latest `EVENT_CAPACITY` events
+    /// are kept for a receiver, one that falls further behind gets `RecvError::Lagged` with the
+    /// number of events it missed and carries on from the oldest one kept.
+    pub async fn events(&self) -> broadcast::Receiver<RaftEvent> {
+        self.details.lock().await.observers.subscribe()
+    }
+
+    /// Shuts the node down, stopping its server, its background tasks and `run`, then persists
+    /// hard state one last time. Resolves once they all stopped, no RPC is served from then on.
+    /// Proposals waiting for commit fail with `Dropped`, later calls with `ShuttingDown`.
+    pub async fn shutdown(&self) -> Result<(), RaftError> {
+        let _ = self.shutdown.signal.send(true);
+        let tasks = std::mem::take(&mut *self.shutdown.tasks.lock().await);
+        for task in tasks {
+            let _ = task.await;
+        }
+
+        let mut details = self.details.lock().await;
+        details.pending.clear();
+        details.persist()?;
+        Ok(())
+    }
+
+    fn is_shut_down(&self) -> bool {
+        *self.shutdown.stopped.borrow()
+    }
+
+    /// Spawns a task that is awaited when the node shuts down.
+    async fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        let mut tasks = self.shutdown.tasks.lock().await;
+        // Tasks that finished meanwhile need not be awaited
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(tokio::spawn(task));
+    }
+
+    /// Snapshots the state machine and compacts the log upto the last applied entry, returning
+    /// index of the snapshot.
+    pub async fn trigger_snapshot(&self) -> Result<u64, RaftError> {
+        snapshot(&self.details, &self.state_machine).await
     }
 
-    pub async fn run(&mut self, config: Config) -> Result<(), Box<dyn Error>> {
-        let (mut clock, mut rng) = (Instant::now(), rand::thread_rng());
+    /// Proposes command to be appended to the replicated log, fails with `NotLeader` carrying address
+    /// of the leader, if known, when called on any other node. With `Config::forward_to_leader`
+    /// followers relay the command to that leader instead, failing with `NotLeader` only while
+    /// they know of none.
+    ///
+    /// The returned future resolves with the index of the entry only once it is committed, that is
+    /// stored by a majority of the cluster and hence never lost, not as soon as the leader appends
+    /// it to its own log. It fails with `Dropped` if the entry is overwritten by a new leader before
+    /// it could be committed, in which case the proposal should be retried.
+    pub async fn propose(&self, command: S::Command) -> Result<u64, ProposeError> {
+        let leader = {
+            let details = self.details.lock().await;
+            if details.forward_to_leader && details.role() != NodeRole::Leader {
+                Some(details.leader_addr.clone())
+            } else {
+                None
+            }
+        };
+        match leader {
+            Some(Some(leader)) => self.forward_proposal(&leader, command.as_bytes()).await,
+            Some(None) => Err(ProposeError::NotLeader { leader: None }),
+            None => {
+                self.propose_entry(EntryKind::Normal, command.as_bytes())
+                    .await
+            }
+        }
+    }
+
+    /// Relays a proposal to the leader at addr, which only ever appends it to its own log, so
+    /// stale leader addresses can't make proposals go around in circles.
+    async fn forward_proposal(&self, leader: &str, data: Vec<u8>) -> Result<u64, ProposeError> {
+        if self.is_shut_down() {
+            return Err(ProposeError::ShuttingDown);
+        }
+        debug!(%leader, "forwarding proposal to leader");
+        let transport = self.details.lock().await.transport.clone();
+        match transport
+            .propose(leader, Request::new(Byte { body: data }))
+            .await
+        {
+            Ok(reply) => Ok(reply.into_inner().index),
+            // Leader tells apart the errors clients act on by their code
+            Err(status) if status.code() == Code::Aborted => Err(ProposeError::Dropped),
+            Err(status) if status.code() == Code::FailedPrecondition => {
+                Err(ProposeError::NotLeader { leader: None })
+            }
+            Err(status) => Err(status.into()),
+        }
+    }
+
+    /// Index upto which the state machine must have applied entries for a read to observe every
+    /// write committed before it was issued, following the ReadIndex protocol. Fails with
+    /// `NotLeader` on any other node than the leader, and with `NoQuorum` if the leader can't
+    /// confirm it still leads.
+    ///
+    /// The leader takes its commit index, committing a no-op of its term first if it hasn't
+    /// committed anything since it was elected, then confirms leadership through a round of
+    /// heartbeats, skipped while it holds a lease if `Config::lease_reads` is enabled. Resolves
+    /// once the state machine applied entries upto that index.
+    pub async fn read_index(&self) -> Result<u64, RaftError> {
+        self.commit_own_term().await?;
+        let (read_index, leased) = {
+            let details = self.details.lock().await;
+            (details.commit_index, details.has_lease())
+        };
+        // No other node can lead before the lease expires, sparing a round of heartbeats
+        let confirmed = leased || confirm_leadership(&self.details).await.unwrap_or(false);
+        {
+            let details = self.details.lock().await;
+            if details.role() != NodeRole::Leader {
+                return Err(RaftError::NotLeader {
+                    leader: details.leader_addr.clone(),
+                });
+            } else if !confirmed {
+                return Err(RaftError::NoQuorum);
+            }
+        }
+
+        self.wait_applied(read_index).await;
+        Ok(read_index)
+    }
+
+    /// Runs query against the state machine once it reflects every write committed before the
+    /// read was issued, see `read_index`.
+    pub async fn linearizable_read<R>(&self, query: impl FnOnce(&S) -> R) -> Result<R, RaftError> {
+        self.read_index().await?;
+        let state_machine = self.state_machine.lock().await;
+        Ok(query(&state_machine))
+    }
+
+    /// Runs query against the state machine like `linearizable_read`, but skips the round of
+    /// heartbeats while the leader holds a lease, enabled by `Config::lease_reads`. Fails with
+    /// `LeaseExpired` otherwise, in which case `linearizable_read` can still serve the read.
+    pub async fn read_local<R>(&self, query: impl FnOnce(&S) -> R) -> Result<R, RaftError> {
+        self.commit_own_term().await?;
+        let read_index = {
+            let details = self.details.lock().await;
+            if details.role() != NodeRole::Leader {
+                return Err(RaftError::NotLeader {
+                    leader: details.leader_addr.clone(),
+                });
+            } else if !details.has_lease() {
+                return Err(RaftError::LeaseExpired);
+            }
+            details.commit_index
+        };
+
+        self.wait_applied(read_index).await;
+        let state_machine = self.state_machine.lock().await;
+        Ok(query(&state_machine))
+    }
+
+    /// Leads a cluster of which this node is the only member right away, committing its membership
+    /// as the first entry of the log. Nodes that have peers, entries or a term already, as when
+    /// restarted or joined to another cluster, are left alone.
+    async fn lead_alone(&self) -> Result<(), RaftError> {
+        {
+            let details = self.details.lock().await;
+            if !details.peers().is_empty()
+                || details.standing != Standing::Voter
+                || details.last_log_index() > 0
+                || details.current_term > 0
+            {
+                return Ok(());
+            }
+        }
+        campaign(&self.details).await?;
+        let membership = self.details.lock().await.membership();
+        let index = self
+            .propose_entry(EntryKind::Config, membership.as_bytes())
+            .await?;
+        info!(index, "bootstrapped cluster");
+        Ok(())
+    }
+
+    /// Makes sure the leader committed an entry of its own term, committing a no-op if not, as
+    /// entries of previous terms may be committed without it knowing of it yet.
+    async fn commit_own_term(&self) -> Result<(), RaftError> {
+        if self.is_shut_down() {
+            return Err(RaftError::ShuttingDown);
+        }
+        let committed = {
+            let details = self.details.lock().await;
+            if details.role() != NodeRole::Leader {
+                return Err(RaftError::NotLeader {
+                    leader: details.leader_addr.clone(),
+                });
+            }
+            details.term_at(details.commit_index) == Some(details.current_term)
+        };
+        if !committed {
+            self.propose_entry(EntryKind::Noop, Vec::new()).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Index of the last entry applied to the state machine.
+    pub async fn last_applied(&self) -> u64 {
+        self.details.lock().await.last_applied
+    }
+
+    /// Resolves once the state machine applied all entries upto index.
+    pub async fn wait_applied(&self, index: u64) {
+        let mut applied = self.details.lock().await.applied.subscribe();
+        while *applied.borrow_and_update() < index {
+            // Sender lives as long as the node
+            if applied.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Adds node at addr to the cluster as a voting member, through a configuration entry
+    /// replicated like any proposal. Only one membership change can be in progress at a time,
+    /// others fail with `MembershipChanging`. Resolves once the entry is committed, from then on
+    /// the leader replicates to the new node and counts it towards the quorum.
+    ///
+    /// A node with an empty log holds up commits until it catches up, prefer `add_learner`
+    /// followed by `promote` for new nodes. The new node should be started with addresses of the
+    /// existing members, so that it can't elect itself meanwhile.
+    pub async fn add_node(&self, addr: String) -> Result<u64, RaftError> {
+        self.change_membership(MembershipChange::Add, addr).await
+    }
+
+    /// Adds node at addr to the cluster as a learner, which receives the log like any member but
+    /// doesn't vote nor count towards the quorum, just like `add_node` otherwise.
+    pub async fn add_learner(&self, addr: String) -> Result<u64, RaftError> {
+        self.change_membership(MembershipChange::AddLearner, addr)
+            .await
+    }
+
+    /// Promotes learner at addr to a voting member, once its log is within `promotion_lag`
+    /// entries of the leader's. Fails with `LearnerBehind` if it hasn't caught up yet. Leaders do
+    /// so on their own for learners that caught up, unless `auto_promote` is disabled.
+    pub async fn promote(&self, addr: String) -> Result<u64, RaftError> {
+        self.change_membership(MembershipChange::Promote, addr)
+            .await
+    }
+
+    /// Removes node at addr from the cluster, through a configuration entry just like `add_node`.
+    /// Once the entry is committed the leader stops replicating to the node, after a last
+    /// AppendEntries which tells it of its removal so that it stops campaigning. A leader that
+    /// removes itself steps down once the remaining members learnt of the commit.
+    pub async fn remove_node(&self, addr: String) -> Result<u64, RaftError> {
+        self.change_membership(MembershipChange::Remove, addr).await
+    }
+
+    /// Asks the member of a cluster at addr to add this node to it as a learner, forwarded to the
+    /// leader if addr isn't it. Resolves once this node is a learner, to be promoted by the leader.
+    pub async fn join(&self, addr: String) -> Result<(), RaftError> {
+        self.request_membership(MembershipChange::AddLearner, addr)
+            .await?;
+        // Leader may have committed the change before it first reached this node
+        let mut status = self.subscribe().await;
+        while status.borrow_and_update().leader.is_none() {
+            if status.changed().await.is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Asks the member of a cluster at addr to remove this node from it, forwarded to the leader
+    /// if addr isn't it. Resolves once this node is no longer a member.
+    pub async fn leave(&self, addr: String) -> Result<(), RaftError> {
+        self.request_membership(MembershipChange::Remove, addr)
+            .await
+    }
+
+    /// Hands leadership over to the voting member at target, for instance before restarting the
+    /// leader for maintenance. Proposals are refused with `Transferring` while target is brought
+    /// up to date and then told to campaign right away. Resolves once target is known to lead,
+    /// fails with `Timeout` if that doesn't happen within an election timeout, after which this
+    /// node resumes leading if it still can.
+    pub async fn transfer_leadership(&self, target: String) -> Result<(), RaftError> {
+        let deadline = {
+            let mut details = self.details.lock().await;
+            if details.role() != NodeRole::Leader {
+                return Err(RaftError::NotLeader {
+                    leader: details.leader_addr.clone(),
+                });
+            } else if target == details.addr {
+                return Ok(());
+            } else if !details.cluster.contains(&target) {
+                return Err(RaftError::NotVoter);
+            } else if details.transferee.is_some() {
+                return Err(RaftError::Transferring);
+            }
+            details.transferee = Some(target.clone());
+            details.lease = None;
+            details.clock.now() + details.election_timeout_min
+        };
+
+        let transferred = self.hand_over(&target, deadline).await;
+        let mut details = self.details.lock().await;
+        if details.transferee.as_ref() == Some(&target) {
+            details.transferee = None;
+        }
+        transferred
+    }
+
+    async fn hand_over(&self, target: &str, deadline: Instant) -> Result<(), RaftError> {
+        let clock = self.details.lock().await.clock.clone();
+        // Target must have every entry, so that it can win the election and nothing is lost
         loop {
-            if clock.elapsed() > Duration::from_secs(config.new_rand_election_timeout()) {
-                clock = Instant::now();
-                self.details.lock().await.start_election().await?;
+            {
+                let details = self.details.lock().await;
+                if details.role() != NodeRole::Leader {
+                    return Err(RaftError::NotLeader {
+                        leader: details.leader_addr.clone(),
+                    });
+                }
             }
+            let _ = replicate(&self.details).await;
+            let caught_up = {
+                let details = self.details.lock().await;
+                let matched = details.match_index.get(target).copied().unwrap_or(0);
+                matched == details.last_log_index()
+            };
+            if caught_up {
+                let _ = timeout_now(&self.details, target).await;
+                break;
+            }
+            if clock.now() >= deadline {
+                return Err(RaftError::Timeout);
+            }
+            clock.sleep(Duration::from_millis(10)).await;
+        }
+
+        // Target's vote request makes this node step down, its first heartbeat shows it leads
+        while clock.now() < deadline {
+            {
+                let details = self.details.lock().await;
+                if details.leader_addr.as_deref() == Some(target) {
+                    return Ok(());
+                }
+            }
+            clock.sleep(Duration::from_millis(10)).await;
         }
+        Err(RaftError::Timeout)
     }
-}
 
-#[tonic::async_trait]
-impl Raft for RaftNode {
-    async fn request_vote(
+    async fn request_membership(
         &self,
-        request: Request<VoteRequest>,
-    ) -> Result<Response<VoteReply>, Status> {
-        let request = request.into_inner();
-        let details = self.details.lock().await;
-        if request.term < details.current_term {
-            return Ok(Response::new(VoteReply {
+        change: MembershipChange,
+        addr: String,
+    ) -> Result<(), RaftError> {
+        let (id, local_addr, transport) = {
+            let details = self.details.lock().await;
+            (details.id, details.addr.clone(), details.transport.clone())
+        };
+        transport.validate_addr(&addr)?;
+        let mut request = Request::new(Byte {
+            body: local_addr.into_bytes(),
+        });
+        request
+            .metadata_mut()
+            .insert(NODE_ID, id.to_string().parse().unwrap());
+        match change {
+            MembershipChange::Remove => transport.leave(&addr, request).await?,
+            _ => transport.join(&addr, request).await?,
+        };
+
+        Ok(())
+    }
+
+    async fn change_membership(
+        &self,
+        change: MembershipChange,
+        addr: String,
+    ) -> Result<u64, RaftError> {
+        let membership = {
+            let details = self.details.lock().await;
+            details.transport.validate_addr(&addr)?;
+            if details.role() != NodeRole::Leader {
+                return Err(RaftError::NotLeader {
+                    leader: details.leader_addr.clone(),
+                });
+            } else if details.is_membership_changing() {
+                return Err(RaftError::MembershipChanging);
+            }
+
+            let current = details.membership();
+            let mut membership = current.clone();
+            match change {
+                MembershipChange::Add | MembershipChange::Promote
+                    if membership.members.contains(&addr) => {}
+                MembershipChange::Add => {
+                    membership.learners.retain(|learner| *learner != addr);
+                    membership.members.push(addr);
+                }
+                MembershipChange::AddLearner if !membership.contains(&addr) => {
+                    membership.learners.push(addr)
+                }
+                MembershipChange::AddLearner => {}
+                MembershipChange::Promote if !membership.learners.contains(&addr) => {
+                    return Err(RaftError::NotLearner);
+                }
+                MembershipChange::Promote if !details.is_caught_up(&addr) => {
+                    return Err(RaftError::LearnerBehind);
+                }
+                MembershipChange::Promote => {
+                    membership.learners.retain(|learner| *learner != addr);
+                    membership.members.push(addr);
+                }
+                MembershipChange::Remove => {
+                    membership.members.retain(|member| *member != addr);
+                    membership.learners.retain(|learner| *learner != addr);
+                }
+            }
+            if membership == current {
+                return Ok(details.commit_index);
+            }
+            membership
+        };
+
+        self.propose_entry(EntryKind::Config, membership.as_bytes())
+            .await
+    }
+
+    /// Handles an RPC from another node and replies to it, as serving it over gRPC does without
+    /// authenticating its sender, for nodes whose RPCs are carried some other way.
+    pub async fn step(&self, message: Message) -> Result<Reply, Status> {
+        Ok(match message {
+            Message::RequestVote(request) => Reply::RequestVote(self.serve_vote(request).await?),
+            Message::AppendEntries(request) => {
+                Reply::AppendEntries(self.serve_entries(request).await?)
+            }
+            Message::InstallSnapshot(request) => {
+                Reply::InstallSnapshot(self.serve_snapshot(request).await?)
+            }
+            Message::TimeoutNow(request) => Reply::TimeoutNow(self.serve_timeout(request).await?),
+        })
+    }
+
+    /// Steps request of an RPC served over gRPC, replying with the reply of the same RPC.
+    async fn step_rpc<T, R>(&self, request: T) -> Result<Response<R>, Status>
+    where
+        T: Into<Message>,
+        R: TryFrom<Reply, Error = Reply>,
+    {
+        let reply = self.step(request.into()).await?;
+        R::try_from(reply)
+            .map(Response::new)
+            .map_err(|reply| Status::internal(format!("Mismatched reply {:?}", reply)))
+    }
+
+    #[instrument(name = "request_vote", skip_all, fields(
+        node_id = field::Empty,
+        term = request.term,
+        candidate = request.candidate_id,
+        pre_vote = request.pre_vote,
+    ))]
+    async fn serve_vote(&self, request: VoteRequest) -> Result<VoteReply, Status> {
+        let mut details = self.details.lock().await;
+        Span::current().record("node_id", details.id);
+        details
+            .claim_id(request.candidate_id, &request.candidate_addr)
+            .map_err(|e| Status::already_exists(e.to_string()))?;
+        // Removed nodes may not know they were removed, they must not disrupt a live leader
+        let non_member = !details.cluster.contains(&request.candidate_addr);
+        if request.term < details.current_term || (non_member && details.has_leader()) {
+            debug!(
+                current_term = details.current_term,
+                non_member, "vote denied"
+            );
+            return Ok(VoteReply {
                 term: details.current_term,
                 grant: false,
-            }));
-        } else if details.voted_for == details.id {
-            return Ok(Response::new(VoteReply {
+                id: details.id,
+            });
+        }
+        if request.pre_vote {
+            // Would vote for a candidate with an up-to-date log in its next term, unless a live
+            // leader exists, without changing any state
+            let grant = request.term > details.current_term
+                && !details.has_leader()
+                && details.is_log_up_to_date(request.last_log_index, request.last_log_term);
+            debug!(
+                current_term = details.current_term,
+                grant, "pre-vote answered"
+            );
+            return Ok(VoteReply {
                 term: details.current_term,
-                grant: true,
-            }));
+                grant,
+                id: details.id,
+            });
+        }
+        let hard_state = (details.current_term, details.voted_for);
+        if request.term > details.current_term {
+            details.step_down(request.term);
+        }
+
+        // Vote only once per term and only for candidates whose log isn't behind this node's
+        let candidate = request.candidate_id;
+        let can_vote = match details.voted_for {
+            Some(voted_for) => voted_for == candidate,
+            None => true,
+        };
+        let grant =
+            can_vote && details.is_log_up_to_date(request.last_log_index, request.last_log_term);
+        if grant {
+            details.voted_for = Some(candidate);
+            // Granting a vote defers this node's own candidacy
+            details.reset_election_timer();
+        }
+        debug!(role = ?details.role(), voted_for = ?details.voted_for, grant, "vote answered");
+
+        // Term and vote must be on disk before the reply leaves this node
+        if hard_state != (details.current_term, details.voted_for) {
+            details
+                .persist()
+                .map_err(|e| Status::internal(e.to_string()))?;
         }
 
-        Ok(Response::new(VoteReply {
+        Ok(VoteReply {
             term: details.current_term,
-            grant: false,
-        }))
+            grant,
+            id: details.id,
+        })
     }
 
-    async fn append_entries(
-        &self,
-        request: Request<EntryRequest>,
-    ) -> Result<Response<EntryReply>, Status> {
-        let request = request.into_inner();
+    #[instrument(name = "append_entries", skip_all, fields(
+        node_id = field::Empty,
+        term = request.term,
+        leader = request.id,
+        prev_log_index = request.prev_log_index,
+    ))]
+    async fn serve_entries(&self, request: EntryRequest) -> Result<EntryReply, Status> {
         let mut details = self.details.lock().await;
+        Span::current().record("node_id", details.id);
+        details
+            .claim_id(request.id, &request.leader_addr)
+            .map_err(|e| Status::already_exists(e.to_string()))?;
         if request.term < details.current_term {
-            return Ok(Response::new(EntryReply {
+            debug!(current_term = details.current_term, "rejected stale leader");
+            return Ok(EntryReply {
                 term: details.current_term,
                 success: false,
-            }));
-        } else if request.prev_index > details.commit_index {
-            return Ok(Response::new(EntryReply {
+                conflict_index: 0,
+                conflict_term: 0,
+                id: details.id,
+            });
+        }
+        let current_term = details.current_term;
+        details.follow(request.term, request.id, request.leader_addr);
+
+        let last_new_index = match request.entries.last() {
+            Some(entry) => entry.index,
+            None => request.prev_log_index,
+        };
+        let entries = request
+            .entries
+            .into_iter()
+            .map(|entry| {
+                trace!(index = entry.index, term = entry.term, "received entry");
+                LogEntry::from(entry)
+            })
+            .collect();
+        let success = details
+            .append_log(request.prev_log_index, request.prev_log_term, entries)
+            .map_err(|e| Status::internal(e.to_string()))?;
+        if success && request.commit_index > details.commit_index {
+            details
+                .commit(min(request.commit_index, last_new_index))
+                .map_err(|e| Status::internal(e.to_string()))?;
+        }
+        let (conflict_index, conflict_term) = match success {
+            true => (0, 0),
+            false => details.conflict(request.prev_log_index),
+        };
+        if success {
+            trace!(commit_index = details.commit_index, "appended entries");
+        } else {
+            debug!(
+                conflict_index,
+                conflict_term, "rejected entries, log doesn't match"
+            );
+        }
+
+        // Adopted term must be on disk before the reply leaves this node, appended entries already are
+        if current_term != details.current_term {
+            details
+                .persist()
+                .map_err(|e| Status::internal(e.to_string()))?;
+        }
+
+        Ok(EntryReply {
+            term: details.current_term,
+            success,
+            conflict_index,
+            conflict_term,
+            id: details.id,
+        })
+    }
+
+    #[instrument(name = "install_snapshot", skip_all, fields(
+        node_id = field::Empty,
+        term = request.term,
+        leader = request.id,
+        offset = request.offset,
+    ))]
+    async fn serve_snapshot(&self, request: SnapshotRequest) -> Result<SnapshotReply, Status> {
+        let mut details = self.details.lock().await;
+        Span::current().record("node_id", details.id);
+        details
+            .claim_id(request.id, &request.leader_addr)
+            .map_err(|e| Status::already_exists(e.to_string()))?;
+        if request.term < details.current_term {
+            return Ok(SnapshotReply {
                 term: details.current_term,
-                success: false,
-            }));
-        } else if request.commit_index > details.commit_index {
-            let last_entry_index = match details.log.last() {
-                Some(entry) => entry.0,
-                None => 0,
-            };
-            details.commit_index = min(request.commit_index, last_entry_index);
+                id: details.id,
+            });
+        }
+        let current_term = details.current_term;
+        details.follow(request.term, request.id, request.leader_addr);
+        if current_term != details.current_term {
+            details
+                .persist()
+                .map_err(|e| Status::internal(e.to_string()))?;
+        }
+
+        // Buffer chunks in order, a transfer starts over whenever the first chunk arrives
+        if request.offset == 0 {
+            details.snapshot_buffer.clear();
+        }
+        if request.offset != details.snapshot_buffer.len() as u64 {
+            return Err(Status::failed_precondition("Snapshot chunk out of order"));
+        }
+        details.snapshot_buffer.extend_from_slice(&request.data);
+        if !request.done {
+            return Ok(SnapshotReply {
+                term: details.current_term,
+                id: details.id,
+            });
+        }
+
+        let meta = raft_proto::SnapshotMeta {
+            index: request.last_included_index,
+            term: request.last_included_term,
+            membership: request.membership,
+        };
+        let meta =
+            SnapshotMeta::try_from(meta).map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let snapshot = Snapshot {
+            index: meta.index,
+            term: meta.term,
+            membership: meta.membership,
+            data: std::mem::take(&mut details.snapshot_buffer),
+        };
+        // State machine is always locked before details, so the apply task can't deadlock with this
+        drop(details);
+        let mut state_machine = self.state_machine.lock().await;
+        let mut details = self.details.lock().await;
+        if snapshot.index > details.snapshot.index {
+            // Snapshots the state machine can't restore are refused before the log is replaced,
+            // and it goes back to its previous state if the log can't be
+            let previous = state_machine.snapshot();
+            state_machine
+                .restore(&snapshot.data)
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            if let Err(e) = details.install_snapshot(snapshot.clone()) {
+                let _ = state_machine.restore(&previous);
+                return Err(Status::internal(e.to_string()));
+            }
+            details.emit(RaftEvent::SnapshotInstalled {
+                index: snapshot.index,
+                term: snapshot.term,
+            });
         }
 
-        Ok(Response::new(EntryReply {
+        Ok(SnapshotReply {
             term: details.current_term,
-            success: true,
-        }))
+            id: details.id,
+        })
+    }
+
+    /// Campaigns right away as the leader asked, unless the request is from an older term.
+    #[instrument(name = "timeout_now", skip_all, fields(
+        node_id = field::Empty,
+        term = request.term,
+        leader = request.id,
+    ))]
+    async fn serve_timeout(&self, request: TimeoutRequest) -> Result<TimeoutReply, Status> {
+        let details = self.details.lock().await;
+        Span::current().record("node_id", details.id);
+        let term = details.current_term;
+        if request.term < term || details.standing != Standing::Voter {
+            return Ok(TimeoutReply { term });
+        }
+        drop(details);
+
+        // Leader awaits the reply, while the election needs its vote. Voters still hear from the
+        // leader, so a pre-vote would fail. Nodes shutting down stop waiting on votes
+        let (node, mut stopped) = (self.clone(), self.shutdown.stopped.clone());
+        let election = async move {
+            tokio::select! {
+                _ = campaign(&node.details) => {}
+                _ = stopped.changed() => {}
+            }
+        };
+        self.spawn(election.in_current_span()).await;
+
+        Ok(TimeoutReply { term })
+    }
+
+    /// Serves Join and Leave, forwarding to the leader if this node isn't it. Forwarded requests
+    /// aren't forwarded again, so stale leader addresses can't make requests go around in circles.
+    async fn serve_membership(
+        &self,
+        change: MembershipChange,
+        request: Request<Byte>,
+    ) -> Result<Response<Null>, Status> {
+        let forwarded = request.metadata().contains_key(FORWARDED);
+        let addr = String::from_utf8(request.get_ref().body.clone())
+            .map_err(|_| Status::invalid_argument("Address must be utf-8"))?;
+        self.details
+            .lock()
+            .await
+            .transport
+            .validate_addr(&addr)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        // Nodes only ask to join or leave for themselves, members forward on their behalf
+        let sender = if forwarded { None } else { Some(addr.as_str()) };
+        self.authenticate(&request, sender).await?;
+        Span::current()
+            .record("node_id", self.details.lock().await.id)
+            .record("addr", addr.as_str());
+        // Joining nodes tell their id, which no member may already go by
+        let id = request
+            .metadata()
+            .get(NODE_ID)
+            .and_then(|id| id.to_str().ok()?.parse::<u64>().ok());
+        if let (MembershipChange::AddLearner, Some(id)) = (change, id) {
+            self.details
+                .lock()
+                .await
+                .claim_id(id, &addr)
+                .map_err(|e| Status::already_exists(e.to_string()))?;
+        }
+
+        match self.change_membership(change, addr.clone()).await {
+            Ok(index) => {
+                info!(index, "membership changed");
+                Ok(Response::new(Null {}))
+            }
+            Err(RaftError::NotLeader {
+                leader: Some(leader),
+            }) if !forwarded => {
+                debug!(%leader, "forwarding to leader");
+                let transport = self.details.lock().await.transport.clone();
+                let mut request = Request::new(Byte {
+                    body: addr.into_bytes(),
+                });
+                request
+                    .metadata_mut()
+                    .insert(FORWARDED, "true".parse().unwrap());
+                if let Some(id) = id {
+                    request
+                        .metadata_mut()
+                        .insert(NODE_ID, id.to_string().parse().unwrap());
+                }
+                match change {
+                    MembershipChange::Remove => transport.leave(&leader, request).await,
+                    _ => transport.join(&leader, request).await,
+                }
+            }
+            Err(e) => {
+                info!(error = %e, "membership change refused");
+                Err(Status::unavailable(e.to_string()))
+            }
+        }
+    }
+
+    /// Fails with `Status::unauthenticated` if peers must be verified and the certificate the
+    /// peer presented doesn't name sender, or a voting member if the request doesn't tell who
+    /// sent it.
+    #[cfg(feature = "tls")]
+    async fn authenticate<T>(
+        &self,
+        request: &Request<T>,
+        sender: Option<&str>,
+    ) -> Result<(), Status> {
+        if !self.verify_peers {
+            return Ok(());
+        }
+        let names = peer_names(request);
+        let named = match sender {
+            Some(sender) => names.iter().any(|name| name == sender),
+            None => {
+                let details = self.details.lock().await;
+                names.iter().any(|name| details.cluster.contains(name))
+            }
+        };
+        if !named {
+            debug!(?names, sender, "peer not authenticated");
+            return Err(Status::unauthenticated(
+                "Certificate doesn't name the sender",
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "tls"))]
+    async fn authenticate<T>(&self, _: &Request<T>, _: Option<&str>) -> Result<(), Status> {
+        Ok(())
+    }
+
+    async fn propose_entry(&self, kind: EntryKind, data: Vec<u8>) -> Result<u64, ProposeError> {
+        if self.is_shut_down() {
+            return Err(ProposeError::ShuttingDown);
+        }
+        let (mut committed, lingering) = {
+            let mut details = self.details.lock().await;
+            if details.role() != NodeRole::Leader {
+                return Err(ProposeError::NotLeader {
+                    leader: details.leader_addr.clone(),
+                });
+            } else if details.transferee.is_some() {
+                return Err(ProposeError::Transferring);
+            }
+
+            let index = details.append_entry(kind, data)?;
+            let (tx, rx) = oneshot::channel();
+            details.pending.insert(index, tx);
+            // Proposals made within the linger are left to the batch task to replicate together,
+            // unless enough piled up to send them right away
+            details.batched += 1;
+            let lingering =
+                !details.batch_linger.is_zero() && details.batched < details.max_batch_entries;
+            if lingering {
+                details.batch.notify_one();
+            }
+            (rx, lingering)
+        };
+
+        // Entries a majority doesn't store now stay pending until a later replication commits
+        // them. Once committed, replies of slower nodes are left to the next heartbeat
+        let replicated = match lingering {
+            true => None,
+            false => tokio::select! {
+                _ = replicate(&self.details) => None,
+                committed = &mut committed => Some(committed),
+            },
+        };
+        match replicated {
+            Some(committed) => committed,
+            None => committed.await,
+        }
+        .map_err(|_| ProposeError::Dropped)
+    }
+
+    /// Applies the settings of config that take effect once a node runs, as `run` does before
+    /// driving it, for nodes driven by `tick` and `step` instead.
+    pub async fn configure(&self, config: &Config) -> Result<(), RaftError> {
+        config.validate()?;
+        let mut details = self.details.lock().await;
+        details.snapshot_chunk_size = config.snapshot_chunk_size;
+        details.snapshot_threshold = config.snapshot_threshold;
+        details.max_append_entries = config.max_append_entries;
+        details.election_timeout_min = config.election_timeout_min();
+        details.election_timeout_max = config.election_timeout_max();
+        details.heartbeat_interval = config.heartbeat_interval();
+        details.auto_promote = config.auto_promote;
+        details.promotion_lag = config.promotion_lag;
+        details.rpc_timeout = config.rpc_timeout();
+        details.retry_backoff = (config.retry_backoff_min(), config.retry_backoff_max());
+        details.pre_vote = config.pre_vote;
+        details.forward_to_leader = config.forward_to_leader;
+        details.batch_linger = config.batch_linger();
+        details.max_batch_entries = config.max_batch_entries;
+        details.lease_duration = config.lease_duration();
+        details.health_timeout = config.health_timeout();
+        // Timers start over from now by the node's clock
+        details.reset_election_timer();
+        details.last_round = details.clock.now();
+        details.last_quorum_check = details.last_round;
+
+        Ok(())
+    }
+
+    /// Fires the timers of the node that are due as of its clock's time. Followers and candidates
+    /// that didn't hear from a leader or candidate within the election timeout campaign, if they
+    /// are voting members. Leaders send heartbeats every heartbeat interval, which also carry any
+    /// entries followers are missing, and check they still reach a majority every election
+    /// timeout. Entries committed meanwhile are applied.
+    ///
+    /// `run` ticks whenever the next timer is due, nodes driven by a `ManualClock` tick after
+    /// advancing it instead.
+    #[instrument(skip_all, fields(node_id = field::Empty))]
+    pub async fn tick(&self) -> Result<(), RaftError> {
+        let (heartbeat, campaigning, auto_promote) = {
+            let mut details = self.details.lock().await;
+            Span::current().record("node_id", details.id);
+            let now = details.clock.now();
+            let heartbeat = now >= details.last_round + details.heartbeat_interval;
+            if heartbeat {
+                details.last_round = now;
+                #[cfg(feature = "metrics")]
+                details.metrics().record();
+            }
+            // Any node checks it still hears from a leader meanwhile, to report whether it's serving
+            if now >= details.last_quorum_check + details.election_timeout_min {
+                details.last_quorum_check = now;
+                if details.check_quorum() {
+                    info!("lost quorum, stepped down");
+                }
+                details.check_health();
+            }
+            let campaigning = details.role() != NodeRole::Leader
+                && details.standing == Standing::Voter
+                && now >= details.last_heartbeat + details.election_timeout;
+            // Transfers replicate on their own, heartbeats would only hold up the transferee's
+            // vote request while waiting on it to answer them
+            let heartbeat =
+                heartbeat && details.role() == NodeRole::Leader && details.transferee.is_none();
+            (heartbeat, campaigning, details.auto_promote)
+        };
+
+        if campaigning {
+            start_election(&self.details).await?;
+        }
+        if heartbeat {
+            let _ = replicate(&self.details).await;
+            let learner = {
+                let details = self.details.lock().await;
+                match auto_promote && !details.is_membership_changing() {
+                    true => details.caught_up_learner(),
+                    false => None,
+                }
+            };
+            // Promotion commits through heartbeats, so it mustn't hold them up. It is given up on
+            // once the node shuts down, as it may wait on a commit that never comes
+            if let Some(learner) = learner {
+                let (node, mut stopped) = (self.clone(), self.shutdown.stopped.clone());
+                let promotion = async move {
+                    tokio::select! {
+                        promoted = node.promote(learner.clone()) => match promoted {
+                            Ok(index) => info!(%learner, index, "promoted learner"),
+                            Err(e) => debug!(%learner, error = %e, "failed to promote learner"),
+                        },
+                        _ = stopped.changed() => {}
+                    }
+                };
+                self.spawn(promotion.in_current_span()).await;
+            }
+        }
+        self.apply_committed().await;
+
+        Ok(())
+    }
+
+    /// Applies committed entries in order, then snapshots the state machine once enough entries
+    /// were applied since the last snapshot.
+    async fn apply_committed(&self) {
+        {
+            // Holding the state machine throughout keeps installed snapshots from moving last
+            // applied index under the entries being applied
+            let mut state_machine = self.state_machine.lock().await;
+            let entries = {
+                let details = self.details.lock().await;
+                let (from, to) = (details.last_applied, details.commit_index);
+                details.log.entries(from + 1..to + 1)
+            };
+            if let Some(last) = entries.last() {
+                debug!(from = entries[0].index, to = last.index, "applying entries");
+            }
+            for entry in entries {
+                trace!(index = entry.index, kind = ?entry.kind, "applying entry");
+                // Configuration entries already took effect on commit, no-ops carry nothing
+                if entry.kind == EntryKind::Normal {
+                    match S::Command::try_from_bytes(entry.data) {
+                        Ok(command) => {
+                            state_machine.apply(entry.index, command);
+                        }
+                        Err(e) => {
+                            error!(index = entry.index, error = %e, "skipped undecodable entry");
+                            state_machine.decode_failed(entry.index, e);
+                        }
+                    }
+                }
+                self.details.lock().await.set_last_applied(entry.index);
+            }
+        }
+
+        let (applied, snapshot_threshold) = {
+            let details = self.details.lock().await;
+            let applied = details.last_applied - details.snapshot.index;
+            (applied, details.snapshot_threshold)
+        };
+        if snapshot_threshold > 0 && applied >= snapshot_threshold {
+            match snapshot(&self.details, &self.state_machine).await {
+                Ok(index) => info!(index, "took snapshot"),
+                Err(e) => info!(error = %e, "failed to take snapshot"),
+            }
+        }
+    }
+
+    /// Runs the node until it shuts down, ticking whenever its next timer is due, with committed
+    /// entries applied and batched proposals replicated on background tasks meanwhile.
+    pub async fn run(&mut self, config: Config) -> Result<(), RaftError> {
+        if self.is_shut_down() {
+            return Err(RaftError::ShuttingDown);
+        }
+        self.configure(&config).await?;
+        let (clock, id) = {
+            let details = self.details.lock().await;
+            (details.clock.clone(), details.id)
+        };
+
+        // Leader replicates proposals held back for batching once the first of them lingered,
+        // along with those made meanwhile. Permits left while replicating start the next batch
+        let (details, timer) = (self.details.clone(), clock.clone());
+        let (batch, linger) = {
+            let details = self.details.lock().await;
+            (details.batch.clone(), details.batch_linger)
+        };
+        let mut stopped = self.shutdown.stopped.clone();
+        self.spawn(
+            async move {
+                loop {
+                    tokio::select! {
+                        _ = batch.notified() => {}
+                        _ = stopped.changed() => break,
+                    }
+                    tokio::select! {
+                        _ = timer.sleep(linger) => {}
+                        _ = stopped.changed() => break,
+                    }
+                    let batched = {
+                        let details = details.lock().await;
+                        details.role() == NodeRole::Leader && details.batched > 0
+                    };
+                    if batched {
+                        let _ = replicate(&details).await;
+                    }
+                }
+            }
+            .instrument(info_span!("batch", node_id = id)),
+        )
+        .await;
+
+        // Committed entries are applied by a single background task, woken whenever commit index
+        // moves, rather than waiting on the next tick
+        let (node, committed) = (self.clone(), self.details.lock().await.committed.clone());
+        let mut stopped = self.shutdown.stopped.clone();
+        let span = info_span!("apply", node_id = id);
+        let apply_task = async move {
+            loop {
+                // Commits made while entries were being applied left a permit, so none is missed
+                tokio::select! {
+                    _ = committed.notified() => {}
+                    _ = stopped.changed() => break,
+                }
+                node.apply_committed().await;
+            }
+        };
+        self.spawn(apply_task.instrument(span)).await;
+
+        if config.bootstrap {
+            self.lead_alone().await?;
+        }
+
+        // Sleep until the next timer is due, the election deadline moves forward with every
+        // heartbeat
+        let mut stopped = self.shutdown.stopped.clone();
+        loop {
+            let deadline = self.details.lock().await.next_tick();
+            tokio::select! {
+                _ = clock.sleep(deadline.saturating_duration_since(clock.now())) => {}
+                _ = stopped.changed() => return Ok(()),
+            }
+            self.tick().await?;
+        }
+    }
+}
+
+/// Services whose health is reported to gRPC health checks, the whole server and the RPCs
+/// between nodes.
+const HEALTH_SERVICES: [&str; 2] = ["", "raft.Raft"];
+
+/// Metadata key marking requests forwarded to the leader by another node.
+const FORWARDED: &str = "raft-forwarded";
+
+/// Metadata key carrying the id of a node asking to join.
+const NODE_ID: &str = "raft-node-id";
+
+/// RPC from another node, for `RaftNode::step`, converted from its request with `From`.
+#[derive(Clone, Debug)]
+pub enum Message {
+    RequestVote(VoteRequest),
+    AppendEntries(EntryRequest),
+    InstallSnapshot(SnapshotRequest),
+    TimeoutNow(TimeoutRequest),
+}
+
+/// Reply of `RaftNode::step` to the RPC of the same name. Converts into the reply of that RPC
+/// with `TryFrom`, which gives it back for any other.
+#[derive(Clone, Debug)]
+pub enum Reply {
+    RequestVote(VoteReply),
+    AppendEntries(EntryReply),
+    InstallSnapshot(SnapshotReply),
+    TimeoutNow(TimeoutReply),
+}
+
+impl From<VoteRequest> for Message {
+    fn from(request: VoteRequest) -> Self {
+        Self::RequestVote(request)
+    }
+}
+
+impl From<EntryRequest> for Message {
+    fn from(request: EntryRequest) -> Self {
+        Self::AppendEntries(request)
+    }
+}
+
+impl From<SnapshotRequest> for Message {
+    fn from(request: SnapshotRequest) -> Self {
+        Self::InstallSnapshot(request)
+    }
+}
+
+impl From<TimeoutRequest> for Message {
+    fn from(request: TimeoutRequest) -> Self {
+        Self::TimeoutNow(request)
+    }
+}
+
+impl TryFrom<Reply> for VoteReply {
+    type Error = Reply;
+
+    fn try_from(reply: Reply) -> Result<Self, Reply> {
+        match reply {
+            Reply::RequestVote(reply) => Ok(reply),
+            reply => Err(reply),
+        }
+    }
+}
+
+impl TryFrom<Reply> for EntryReply {
+    type Error = Reply;
+
+    fn try_from(reply: Reply) -> Result<Self, Reply> {
+        match reply {
+            Reply::AppendEntries(reply) => Ok(reply),
+            reply => Err(reply),
+        }
+    }
+}
+
+impl TryFrom<Reply> for SnapshotReply {
+    type Error = Reply;
+
+    fn try_from(reply: Reply) -> Result<Self, Reply> {
+        match reply {
+            Reply::InstallSnapshot(reply) => Ok(reply),
+            reply => Err(reply),
+        }
+    }
+}
+
+impl TryFrom<Reply> for TimeoutReply {
+    type Error = Reply;
+
+    fn try_from(reply: Reply) -> Result<Self, Reply> {
+        match reply {
+            Reply::TimeoutNow(reply) => Ok(reply),
+            reply => Err(reply),
+        }
     }
+}
+
+/// Changes to membership of the cluster, Join and Leave request AddLearner and Remove.
+#[derive(Copy, Clone)]
+enum MembershipChange {
+    Add,
+    AddLearner,
+    Promote,
+    Remove,
+}
+
+/// Names the certificate a peer presented is issued for, its common names and DNS names. Empty if
+/// the request didn't come over TLS.
+#[cfg(feature = "tls")]
+fn peer_names<T>(request: &Request<T>) -> Vec<String> {
+    use x509_parser::{extensions::GeneralName, parse_x509_certificate};
+
+    let certs = match request.peer_certs() {
+        Some(certs) => certs,
+        None => return vec![],
+    };
+    // Peer's own certificate comes first, followed by those that signed it
+    let cert = match certs
+        .first()
+        .map(|cert| parse_x509_certificate(cert.get_ref()))
+    {
+        Some(Ok((_, cert))) => cert,
+        _ => return vec![],
+    };
+    let mut names: Vec<String> = cert
+        .subject()
+        .iter_common_name()
+        .filter_map(|name| name.as_str().ok())
+        .map(str::to_string)
+        .collect();
+    if let Ok(Some(alt_names)) = cert.subject_alternative_name() {
+        for name in &alt_names.value.general_names {
+            if let GeneralName::DNSName(name) = name {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names
+}
+
+/// Snapshots the state machine as of the last applied entry and compacts the log upto it.
+async fn snapshot<S: StateMachine>(
+    details: &Mutex<RaftDetails>,
+    state_machine: &Mutex<S>,
+) -> Result<u64, RaftError> {
+    // Holding the state machine keeps the apply task from moving last applied index meanwhile
+    let state_machine = state_machine.lock().await;
+    let data = state_machine.snapshot();
+    let mut details = details.lock().await;
+    let index = details.last_applied;
+    details.compact(index, data)?;
+
+    Ok(details.snapshot.index)
+}
 
+#[tonic::async_trait]
+impl<S: StateMachine> Raft for RaftNode<S> {
+    async fn request_vote(
+        &self,
+        request: Request<VoteRequest>,
+    ) -> Result<Response<VoteReply>, Status> {
+        self.authenticate(&request, Some(&request.get_ref().candidate_addr))
+            .await?;
+        self.step_rpc(request.into_inner()).await
+    }
+
+    async fn append_entries(
+        &self,
+        request: Request<EntryRequest>,
+    ) -> Result<Response<EntryReply>, Status> {
+        self.authenticate(&request, Some(&request.get_ref().leader_addr))
+            .await?;
+        self.step_rpc(request.into_inner()).await
+    }
+
+    async fn install_snapshot(
+        &self,
+        request: Request<SnapshotRequest>,
+    ) -> Result<Response<SnapshotReply>, Status> {
+        self.authenticate(&request, Some(&request.get_ref().leader_addr))
+            .await?;
+        self.step_rpc(request.into_inner()).await
+    }
+
+    /// Campaigns right away as the leader asked, unless the request is from an older term.
+    async fn timeout_now(
+        &self,
+        request: Request<TimeoutRequest>,
+    ) -> Result<Response<TimeoutReply>, Status> {
+        self.authenticate(&request, None).await?;
+        self.step_rpc(request.into_inner()).await
+    }
+
+    /// Adds node at address carried in body to the cluster as a learner.
+    #[instrument(skip_all, fields(node_id = field::Empty, addr = field::Empty))]
     async fn join(&self, request: Request<Byte>) -> Result<Response<Null>, Status> {
-        Ok(Response::new(Null {}))
+        self.serve_membership(MembershipChange::AddLearner, request)
+            .await
+    }
+
+    /// Removes node at address carried in body from the cluster.
+    #[instrument(skip_all, fields(node_id = field::Empty, addr = field::Empty))]
+    async fn leave(&self, request: Request<Byte>) -> Result<Response<Null>, Status> {
+        self.serve_membership(MembershipChange::Remove, request)
+            .await
+    }
+
+    /// Appends command carried in body, as forwarded by a follower, replying with its index once
+    /// committed.
+    #[instrument(skip_all, fields(node_id = field::Empty))]
+    async fn propose(&self, request: Request<Byte>) -> Result<Response<ProposeReply>, Status> {
+        self.authenticate(&request, None).await?;
+        Span::current().record("node_id", self.details.lock().await.id);
+        match self
+            .propose_entry(EntryKind::Normal, request.into_inner().body)
+            .await
+        {
+            Ok(index) => Ok(Response::new(ProposeReply { index })),
+            Err(e @ RaftError::Dropped) => Err(Status::aborted(e.to_string())),
+            Err(e @ RaftError::NotLeader { .. }) => Err(Status::failed_precondition(e.to_string())),
+            Err(e) => Err(Status::unavailable(e.to_string())),
+        }
     }
 }
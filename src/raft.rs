@@ -1,66 +1,1696 @@
-use std::{collections::HashMap, error::Error};
-use tonic::Request;
+use rand::Rng;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::TryFrom,
+    fs,
+    future::Future,
+    io,
+    path::PathBuf,
+    sync::Arc,
+};
+use tokio::{
+    sync::{broadcast, mpsc, oneshot, watch, Mutex, Notify},
+    time::{Duration, Instant},
+};
+use tonic::{Request, Status};
+use tracing::{debug, error, field, info, instrument, Instrument, Span};
 
-use crate::raft_proto::{raft_client::RaftClient, VoteRequest};
+use crate::{
+    clock::{Clock, TokioClock},
+    config::Config,
+    error::{DecodeError, RaftError},
+    raft_proto::{
+        self, Entry, EntryReply, EntryRequest, SnapshotRequest, TimeoutRequest, VoteRequest,
+    },
+    storage::{FileLog, FileStorage, HardState, LogStore, MemLog, Membership, Snapshot, Storage},
+    transport::{GrpcTransport, RaftTransport},
+};
 
 /// A trait to ensures interfaces necessart in types that can be transformed into byte based messages for
 /// easy transport over the network, ensuring raft based consensus of cluster state.
 pub trait RaftData {
     fn as_bytes(&self) -> Vec<u8>;
-    fn from_bytes(_: Vec<u8>) -> Self;
+
+    /// Decodes bytes written by `as_bytes`, failing on bytes that aren't a valid encoding.
+    /// Committed entries are decoded with this before they are applied.
+    fn try_from_bytes(bytes: Vec<u8>) -> Result<Self, DecodeError>
+    where
+        Self: Sized;
+
+    /// Decodes bytes as `try_from_bytes` does, but panics on bytes that aren't a valid encoding.
+    fn from_bytes(bytes: Vec<u8>) -> Self
+    where
+        Self: Sized,
+    {
+        Self::try_from_bytes(bytes).unwrap_or_else(|e| panic!("{}", e))
+    }
 }
 
-/// Possible server states within a raft cluster
+/// Raw bytes, for state machines that decode commands themselves.
+impl RaftData for Vec<u8> {
+    fn as_bytes(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn try_from_bytes(bytes: Vec<u8>) -> Result<Self, DecodeError> {
+        Ok(bytes)
+    }
+}
+
+/// Possible roles of a node within a raft cluster
 /// Follower: Can only respond to requests from nodes of cluster
 /// Candidate: Can only request to be elected Leader of cluster
 /// Leader: Operate until node failure, leads updates to state
-pub enum ServerState {
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NodeRole {
     Follower,
     Candidate,
     Leader,
 }
 
+/// State of a node at some point in time, as returned by `RaftNode::status`.
+#[derive(Debug, Clone)]
+pub struct RaftStatus {
+    pub id: u64,
+    pub role: NodeRole,
+    /// Standing of the node within the membership of the cluster
+    pub standing: Standing,
+    pub current_term: u64,
+    pub commit_index: u64,
+    /// Index of the last entry applied to the state machine
+    pub last_applied: u64,
+    /// Address of the leader of the current term, if known
+    pub leader: Option<String>,
+    /// Addresses of the other voting members of the cluster
+    pub peers: Vec<String>,
+    /// Addresses of the learners of the cluster, other than this node
+    pub learners: Vec<String>,
+    /// Number of RPCs in a row that failed to reach each node, for nodes last found unreachable
+    pub failures: HashMap<String, u32>,
+}
+
+/// Measurements of a node at some point in time, as returned by `RaftNode::metrics`.
+#[derive(Debug, Clone)]
+pub struct RaftMetrics {
+    pub id: u64,
+    pub role: NodeRole,
+    pub current_term: u64,
+    /// Id of the leader of the current term, if known
+    pub leader_id: Option<u64>,
+    pub commit_index: u64,
+    pub last_applied: u64,
+    /// Number of entries in log since the latest snapshot
+    pub log_length: u64,
+    /// Index of the last entry the latest snapshot replaced
+    pub snapshot_index: u64,
+    /// Replication to each other node, as tracked by the leader, empty on any other node
+    pub peers: HashMap<String, PeerMetrics>,
+    /// Number of elections the node started since it was started
+    pub elections_started: u64,
+    /// Number of elections the node won since it was started
+    pub elections_won: u64,
+    /// Number of AppendEntries the node sent as leader since it was started
+    pub heartbeats_sent: u64,
+}
+
+#[cfg(feature = "metrics")]
+impl RaftMetrics {
+    /// Records these through the `metrics` crate as gauges and counters labelled with the node's
+    /// id, for whichever recorder is installed, such as a Prometheus exporter, to expose.
+    pub fn record(&self) {
+        use metrics::{counter, gauge};
+
+        let id = self.id.to_string();
+        let leader = self.role == NodeRole::Leader;
+        gauge!("raft_leader", "id" => id.clone()).set(leader as u8);
+        gauge!("raft_current_term", "id" => id.clone()).set(self.current_term as f64);
+        gauge!("raft_commit_index", "id" => id.clone()).set(self.commit_index as f64);
+        gauge!("raft_last_applied", "id" => id.clone()).set(self.last_applied as f64);
+        gauge!("raft_log_length", "id" => id.clone()).set(self.log_length as f64);
+        gauge!("raft_snapshot_index", "id" => id.clone()).set(self.snapshot_index as f64);
+        for (peer, metrics) in &self.peers {
+            let labels = [("id", id.clone()), ("peer", peer.clone())];
+            gauge!("raft_peer_match_index", &labels).set(metrics.match_index as f64);
+            gauge!("raft_peer_lag", &labels).set(metrics.lag as f64);
+        }
+        counter!("raft_elections_started_total", "id" => id.clone())
+            .absolute(self.elections_started);
+        counter!("raft_elections_won_total", "id" => id.clone()).absolute(self.elections_won);
+        counter!("raft_heartbeats_sent_total", "id" => id).absolute(self.heartbeats_sent);
+    }
+}
+
+/// Replication to a node, as tracked by the leader.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PeerMetrics {
+    /// Index of the last entry known to be stored on the node
+    pub match_index: u64,
+    /// Number of entries of the leader's log that the node is yet to store
+    pub lag: u64,
+}
+
+/// Transitions of a node, as delivered to receivers of `RaftNode::events`. Each is sent once the
+/// transition is complete, in the order the node went through them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RaftEvent {
+    /// Node won the election of term
+    BecameLeader { term: u64 },
+    /// Node stepped down or learnt of a new leader, which is None until it hears from one
+    BecameFollower { leader: Option<String> },
+    /// Node started campaigning in term
+    BecameCandidate { term: u64 },
+    /// Membership of the cluster, including this node, took effect
+    MembershipChanged(Membership),
+    /// Snapshot sent by the leader replaced the state machine and log upto index
+    SnapshotInstalled { index: u64, term: u64 },
+}
+
+/// Number of events kept for receivers that fall behind, older ones are dropped first.
+pub const EVENT_CAPACITY: usize = 64;
+
+/// Consecutive failures to reach a node, which isn't sent RPCs again until retry_at.
+#[derive(Debug, Copy, Clone)]
+pub struct Backoff {
+    pub failures: u32,
+    pub retry_at: Instant,
+}
+
+/// Kinds of entries in the replicated log
+/// Normal: Data proposed by clients, applied to the state machine
+/// Config: Membership of the cluster, taking effect on each node once committed
+/// Noop: Empty entry a leader commits in its own term, to learn which entries are committed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum EntryKind {
+    Normal,
+    Config,
+    Noop,
+}
+
+impl EntryKind {
+    pub fn as_byte(self) -> u8 {
+        match self {
+            EntryKind::Normal => 0,
+            EntryKind::Config => 1,
+            EntryKind::Noop => 2,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(EntryKind::Normal),
+            1 => Some(EntryKind::Config),
+            2 => Some(EntryKind::Noop),
+            _ => None,
+        }
+    }
+}
+
+/// Standing of a node within the membership of the cluster
+/// Voter: Votes in elections and counts towards the quorum, may become Leader
+/// Learner: Only receives the log, catching up before it is promoted to Voter
+/// Removed: No longer part of the cluster
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Standing {
+    Voter,
+    Learner,
+    Removed,
+}
+
+/// An entry of the replicated log, holding data proposed in term to be stored at index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LogEntry {
+    pub index: u64,
+    pub term: u64,
+    pub kind: EntryKind,
+    pub data: Vec<u8>,
+}
+
+impl From<LogEntry> for Entry {
+    fn from(entry: LogEntry) -> Self {
+        Self {
+            index: entry.index,
+            term: entry.term,
+            kind: entry.kind.as_byte() as u32,
+            data: entry.data,
+        }
+    }
+}
+
+impl From<Entry> for LogEntry {
+    fn from(entry: Entry) -> Self {
+        // Kinds this node doesn't know of are taken for normal entries
+        let kind = u8::try_from(entry.kind)
+            .ok()
+            .and_then(EntryKind::from_byte)
+            .unwrap_or(EntryKind::Normal);
+        Self {
+            index: entry.index,
+            term: entry.term,
+            kind,
+            data: entry.data,
+        }
+    }
+}
+
 pub struct RaftDetails {
     pub current_term: u64,
     pub commit_index: u64,
-    pub voted_for: u8,
-    pub votes_recieved: HashMap<u8, bool>,
-    pub state: ServerState,
-    pub id: u8,
-    pub log: Vec<(u64, Vec<u8>)>,
+    /// Index of the last entry applied to the state machine
+    pub last_applied: u64,
+    pub voted_for: Option<u64>,
+    pub votes_recieved: HashMap<u64, bool>,
+    pub role: NodeRole,
+    pub id: u64,
+    pub log: Box<dyn LogStore>,
+    /// Latest snapshot of the state machine, replacing all entries of log upto its index
+    pub snapshot: Snapshot,
+    /// Addresses of all other voting members of the cluster, as of the latest committed configuration
     pub cluster: Vec<String>,
+    /// Addresses of all other learners of the cluster, which are replicated to but don't vote
+    pub learners: Vec<String>,
+    /// Last time a valid leader or candidate was heard from, used to time out elections
+    pub last_heartbeat: Instant,
+    /// Time after last heartbeat at which this node campaigns, drawn anew within the election
+    /// timeout whenever its election timer resets
+    pub election_timeout: Duration,
+    /// Last time the heartbeat timer fired, the leader sends heartbeats when it does
+    pub last_round: Instant,
+    /// Last time the leader checked it still reaches a majority, and any node its health
+    pub last_quorum_check: Instant,
+    /// Last time this node heard from a leader of its term or led itself
+    pub leader_contact: Instant,
+    /// Time without contact to a leader after which the node reports it isn't serving
+    pub health_timeout: Duration,
+    /// Whether the node takes part in the cluster as it should, for gRPC health checks
+    pub serving: watch::Sender<bool>,
+    /// Storage to which hard state is persisted, kept purely in memory if None
+    pub storage: Option<Box<dyn Storage>>,
+    /// Address on which this node serves RPCs
+    pub addr: String,
+    /// Id of the leader of current term, if known
+    pub leader_id: Option<u64>,
+    /// Address of the leader of current term, if known
+    pub leader_addr: Option<String>,
+    /// Proposals waiting for their entries to be committed, keyed by log index
+    pub pending: BTreeMap<u64, oneshot::Sender<u64>>,
+    /// Index of the next entry to send to each node, maintained by the leader
+    pub next_index: HashMap<String, u64>,
+    /// Index of the last entry known to be replicated on each node, maintained by the leader
+    pub match_index: HashMap<String, u64>,
+    /// Nodes being sent a snapshot by the leader, which rounds of AppendEntries skip meanwhile
+    pub snapshotting: HashSet<String>,
+    /// Maximum number of bytes of snapshot sent per InstallSnapshot
+    pub snapshot_chunk_size: usize,
+    /// Maximum number of entries sent per AppendEntries
+    pub max_append_entries: usize,
+    /// Chunks of a snapshot being received from the leader
+    pub snapshot_buffer: Vec<u8>,
+    /// Standing of this node within the cluster, only voters campaign
+    pub standing: Standing,
+    /// Nodes removed from the cluster that the leader is yet to notify of their removal
+    pub departed: Vec<String>,
+    /// Shortest time without hearing from the leader after which a node may campaign
+    pub election_timeout_min: Duration,
+    /// Longest time without hearing from the leader after which a node campaigns
+    pub election_timeout_max: Duration,
+    /// Time between rounds of heartbeats sent by the leader
+    pub heartbeat_interval: Duration,
+    /// Whether the leader promotes learners that caught up on its own
+    pub auto_promote: bool,
+    /// Number of entries applied since the last snapshot at which the state machine is
+    /// snapshotted, never if zero
+    pub snapshot_threshold: u64,
+    /// Most entries a learner's log may be behind the leader's for it to be promoted
+    pub promotion_lag: u64,
+    /// Node to which the leader is transferring leadership, proposals are refused meanwhile
+    pub transferee: Option<String>,
+    /// Whether to hold a pre-vote before each election
+    pub pre_vote: bool,
+    /// Whether proposals made on a follower are relayed to the leader
+    pub forward_to_leader: bool,
+    /// Last time each node replied to the leader in its current term, to check that the leader
+    /// still reaches a majority
+    pub last_ack: HashMap<String, Instant>,
+    /// Time a lease lasts for, from when the acknowledged heartbeats were sent, None if leases are
+    /// disabled
+    pub lease_duration: Option<Duration>,
+    /// Time until which no other node can be elected leader, the leader serves local reads until then
+    pub lease: Option<Instant>,
+    /// Status of the node, subscribers are notified whenever its role, term or leader changes
+    pub events: watch::Sender<RaftStatus>,
+    /// Transitions of the node, for receivers of `RaftNode::events`
+    pub observers: broadcast::Sender<RaftEvent>,
+    /// Carries RPCs to other nodes
+    pub transport: Arc<dyn RaftTransport>,
+    /// Tells the time by which elections, heartbeats and leases are timed
+    pub clock: Arc<dyn Clock>,
+    /// Addresses of the nodes heard from, by the ids they claimed in their requests and replies
+    pub ids: HashMap<u64, String>,
+    /// Nodes that the latest RPCs failed to reach, none are sent RPCs while backing off
+    pub backoff: HashMap<String, Backoff>,
+    /// Longest time to wait for a reply to an RPC before counting it as failed
+    pub rpc_timeout: Duration,
+    /// Shortest and longest time to wait before sending RPCs to a node again after a failure
+    pub retry_backoff: (Duration, Duration),
+    /// Number of elections the node started
+    pub elections_started: u64,
+    /// Number of elections the node won
+    pub elections_won: u64,
+    /// Number of AppendEntries the node sent as leader
+    pub heartbeats_sent: u64,
+    /// Longest time proposals are held back to replicate together, zero replicates each at once
+    pub batch_linger: Duration,
+    /// Number of proposals held back at which they are replicated without lingering any longer
+    pub max_batch_entries: usize,
+    /// Number of proposals held back since the last replication round started
+    pub batched: usize,
+    /// Notified whenever a proposal is held back, waking the batch task
+    pub batch: Arc<Notify>,
+    /// Notified whenever commit index moves, waking the apply task
+    pub committed: Arc<Notify>,
+    /// Index of the last entry applied to the state machine, for those waiting on it
+    pub applied: watch::Sender<u64>,
 }
 
 impl RaftDetails {
-    pub fn new(id: u8, cluster: Vec<String>) -> Self {
+    pub fn new(id: u64, cluster: Vec<String>) -> Self {
+        let (events, _) = watch::channel(RaftStatus {
+            id,
+            role: NodeRole::Follower,
+            standing: Standing::Voter,
+            current_term: 0,
+            commit_index: 0,
+            last_applied: 0,
+            leader: None,
+            peers: cluster.clone(),
+            learners: vec![],
+            failures: HashMap::new(),
+        });
         Self {
             current_term: 0,
             commit_index: 0,
-            voted_for: id,
+            last_applied: 0,
+            voted_for: None,
             votes_recieved: HashMap::new(),
-            state: ServerState::Follower,
+            role: NodeRole::Follower,
             id,
-            log: vec![],
+            log: Box::new(MemLog::new()),
+            snapshot: Snapshot::default(),
             cluster,
+            learners: vec![],
+            last_heartbeat: Instant::now(),
+            election_timeout: Config::default().election_timeout_min(),
+            last_round: Instant::now(),
+            last_quorum_check: Instant::now(),
+            leader_contact: Instant::now(),
+            health_timeout: Config::default().health_timeout(),
+            serving: watch::channel(true).0,
+            storage: None,
+            addr: String::new(),
+            leader_id: None,
+            leader_addr: None,
+            pending: BTreeMap::new(),
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            snapshotting: HashSet::new(),
+            snapshot_chunk_size: 1 << 20,
+            max_append_entries: Config::default().max_append_entries,
+            snapshot_buffer: vec![],
+            standing: Standing::Voter,
+            departed: vec![],
+            election_timeout_min: Config::default().election_timeout_min(),
+            election_timeout_max: Config::default().election_timeout_max(),
+            heartbeat_interval: Config::default().heartbeat_interval(),
+            auto_promote: Config::default().auto_promote,
+            snapshot_threshold: Config::default().snapshot_threshold,
+            promotion_lag: Config::default().promotion_lag,
+            transferee: None,
+            pre_vote: Config::default().pre_vote,
+            forward_to_leader: Config::default().forward_to_leader,
+            last_ack: HashMap::new(),
+            lease_duration: None,
+            lease: None,
+            events,
+            observers: broadcast::channel(EVENT_CAPACITY).0,
+            transport: Arc::new(GrpcTransport::default()),
+            clock: Arc::new(TokioClock),
+            ids: HashMap::new(),
+            backoff: HashMap::new(),
+            rpc_timeout: Config::default().rpc_timeout(),
+            retry_backoff: (
+                Config::default().retry_backoff_min(),
+                Config::default().retry_backoff_max(),
+            ),
+            elections_started: 0,
+            elections_won: 0,
+            heartbeats_sent: 0,
+            batch_linger: Config::default().batch_linger(),
+            max_batch_entries: Config::default().max_batch_entries,
+            batched: 0,
+            batch: Arc::new(Notify::new()),
+            committed: Arc::new(Notify::new()),
+            applied: watch::channel(0).0,
+        }
+    }
+
+    /// Reloads hard state and log persisted by a previous run of the node, starting fresh if
+    /// nothing was persisted. All further changes are persisted to the same storage.
+    pub fn with_storage(
+        id: u64,
+        cluster: Vec<String>,
+        mut storage: Box<dyn Storage>,
+        mut log: Box<dyn LogStore>,
+    ) -> io::Result<Self> {
+        let mut details = Self::new(id, cluster);
+        let hard_state = storage.load()?;
+        details.current_term = hard_state.current_term;
+        details.voted_for = hard_state.voted_for;
+        if let Some(snapshot) = storage.load_snapshot()? {
+            // Finish any compaction interrupted after persisting the snapshot
+            log.compact(snapshot.index, snapshot.term)?;
+            details.snapshot = snapshot;
+        }
+        details.commit_index = hard_state
+            .commit_index
+            .min(log.last_index())
+            .max(details.snapshot.index);
+        details.log = log;
+        details.storage = Some(storage);
+
+        Ok(details)
+    }
+
+    /// Reloads hard state and log persisted in directory at path by a previous run of the node,
+    /// starting fresh if nothing was persisted. All further changes are persisted to path.
+    pub fn recover(id: u64, cluster: Vec<String>, path: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&path)?;
+        let mut storage = FileStorage::new(path.join("hard_state"));
+        let log = FileLog::open(path.join("log"), storage.load()?.commit_index)?;
+        Self::with_storage(id, cluster, Box::new(storage), Box::new(log))
+    }
+
+    /// Term, vote and commit index of the node, as persisted.
+    pub fn hard_state(&self) -> HardState {
+        HardState {
+            current_term: self.current_term,
+            voted_for: self.voted_for,
+            commit_index: self.commit_index,
+        }
+    }
+
+    /// Durably writes hard state to storage, must return before replying to any RPC that changed
+    /// it so that a crash can't make the node forget a vote or regress its term.
+    pub fn persist(&mut self) -> io::Result<()> {
+        let hard_state = self.hard_state();
+        match self.storage.as_mut() {
+            Some(storage) => storage.save(&hard_state),
+            None => Ok(()),
+        }
+    }
+
+    /// Replaces all entries of log upto index with a snapshot of the state machine, taken after
+    /// applying the entry at index. The snapshot is persisted before the log is compacted.
+    pub fn compact(&mut self, index: u64, data: Vec<u8>) -> io::Result<()> {
+        if index <= self.snapshot.index || index > self.last_applied {
+            return Ok(());
         }
+        let snapshot = Snapshot {
+            index,
+            term: self.term_at(index).unwrap_or_default(),
+            membership: self.membership_at(index),
+            data,
+        };
+        if let Some(storage) = self.storage.as_mut() {
+            storage.save_snapshot(&snapshot)?;
+        }
+        self.log.compact(snapshot.index, snapshot.term)?;
+        self.snapshot = snapshot;
+
+        Ok(())
+    }
+
+    /// Replaces state upto the snapshot's index with it, as sent by the leader. Entries following
+    /// the snapshot are retained if the log has an entry matching its index and term, otherwise
+    /// the whole log is discarded. Returns false if the snapshot is older than the current one.
+    pub fn install_snapshot(&mut self, snapshot: Snapshot) -> io::Result<bool> {
+        if snapshot.index <= self.snapshot.index {
+            return Ok(false);
+        }
+        if let Some(storage) = self.storage.as_mut() {
+            storage.save_snapshot(&snapshot)?;
+        }
+        if self.term_at(snapshot.index) != Some(snapshot.term) {
+            let first_index = self.log.first_index();
+            self.truncate_log(first_index)?;
+        }
+        self.log.compact(snapshot.index, snapshot.term)?;
+
+        self.set_last_applied(snapshot.index);
+        self.snapshot = snapshot;
+        if self.commit_index < self.snapshot.index {
+            self.commit(self.snapshot.index)?;
+        }
+        // Configuration committed upto the snapshot is only known through it
+        if let Some(membership) = self.membership_at(self.commit_index) {
+            self.apply_membership(membership);
+        }
+
+        Ok(true)
     }
 
-    pub async fn start_election(&mut self) -> Result<(), Box<dyn Error>> {
-        self.voted_for = self.id;
-        self.votes_recieved.insert(self.id, true);
+    /// Latest membership of the cluster as of index, from configuration entries in log or the
+    /// snapshot. None if membership never changed since the cluster started.
+    pub fn membership_at(&self, index: u64) -> Option<Membership> {
+        let first_index = self.log.first_index();
+        if index >= first_index {
+            let config = self
+                .log
+                .entries(first_index..index + 1)
+                .into_iter()
+                .rev()
+                .find(|entry| entry.kind == EntryKind::Config);
+            if let Some(entry) = config {
+                return Membership::from_bytes(&entry.data).ok();
+            }
+        }
+        self.snapshot.membership.clone()
+    }
 
-        for node in self.cluster.iter_mut() {
-            let res = RaftClient::connect(format!("http://{  }", node))
-                .await?
-                .request_vote(Request::new(VoteRequest {
-                    term: self.current_term + 1,
-                    id: self.id as u64,
-                    last_index: self.log.len() as u64,
-                    last_term: self.current_term,
-                }))
-                .await;
+    /// Membership of the cluster currently in effect, including this node.
+    pub fn membership(&self) -> Membership {
+        let mut membership = Membership {
+            members: self.cluster.clone(),
+            learners: self.learners.clone(),
+        };
+        match self.standing {
+            Standing::Voter => membership.members.insert(0, self.addr.clone()),
+            Standing::Learner => membership.learners.insert(0, self.addr.clone()),
+            Standing::Removed => {}
         }
+        membership
+    }
+
+    /// All other nodes the leader replicates to, voting members followed by learners.
+    pub fn peers(&self) -> Vec<String> {
+        let mut peers = self.cluster.clone();
+        peers.extend(self.learners.iter().cloned());
+        peers
+    }
+
+    /// Replaces the cluster with all members but this node. As Leader, replication to new members
+    /// starts from the end of the log and stops for members that left, once they were notified.
+    pub fn apply_membership(&mut self, membership: Membership) {
+        let event = RaftEvent::MembershipChanged(membership.clone());
+        let addr = self.addr.clone();
+        self.standing = if membership.members.contains(&addr) {
+            Standing::Voter
+        } else if membership.learners.contains(&addr) {
+            Standing::Learner
+        } else {
+            Standing::Removed
+        };
+        let departed = self
+            .peers()
+            .into_iter()
+            .filter(|node| !membership.contains(node));
+        if self.role == NodeRole::Leader {
+            self.departed.extend(departed);
+        } else {
+            for node in departed {
+                self.transport.forget(&node);
+            }
+        }
+        let others = |addrs: Vec<String>| addrs.into_iter().filter(|node| *node != addr).collect();
+        self.ids.retain(|_, node| membership.contains(node));
+        self.cluster = others(membership.members);
+        self.learners = others(membership.learners);
+
+        let (peers, departed) = (self.peers(), self.departed.clone());
+        self.next_index
+            .retain(|node, _| peers.contains(node) || departed.contains(node));
+        self.match_index
+            .retain(|node, _| peers.contains(node) || departed.contains(node));
+        if self.role == NodeRole::Leader {
+            let next_index = self.last_log_index() + 1;
+            for node in peers {
+                self.next_index.entry(node.clone()).or_insert(next_index);
+                self.match_index.entry(node.clone()).or_insert(0);
+                self.last_ack.entry(node).or_insert(self.clock.now());
+            }
+        }
+        self.emit(event);
+    }
+
+    /// Steps down if fewer than a majority of voting members, counting itself if it votes, replied
+    /// within the shortest election timeout, returns true if it did. An isolated leader would
+    /// otherwise keep taking proposals that can't commit, long after the rest of the cluster
+    /// elected another.
+    pub fn check_quorum(&mut self) -> bool {
+        if self.role != NodeRole::Leader {
+            return false;
+        }
+        let live = self
+            .cluster
+            .iter()
+            .filter_map(|node| self.last_ack.get(node))
+            .filter(|ack| self.clock.elapsed(**ack) < self.election_timeout_min)
+            .count();
+        if live + self.own_vote() >= self.quorum() {
+            return false;
+        }
+
+        self.role = NodeRole::Follower;
+        self.leader_id = None;
+        self.leader_addr = None;
+        self.transferee = None;
+        self.publish();
+        true
+    }
+
+    /// Checks if a majority of voting members, counting itself if it votes, replied to the leader
+    /// since round.
+    fn is_acked_since(&self, round: Instant) -> bool {
+        let acked = self
+            .cluster
+            .iter()
+            .filter_map(|node| self.last_ack.get(node))
+            .filter(|ack| **ack >= round)
+            .count();
+        acked + self.own_vote() >= self.quorum()
+    }
+
+    /// Checks if the leader holds a lease, during which no other node can be elected as a
+    /// majority heard from it within the shortest election timeout.
+    pub fn has_lease(&self) -> bool {
+        self.role == NodeRole::Leader && self.lease.is_some_and(|lease| self.clock.now() < lease)
+    }
+
+    /// Checks if learner's log is close enough to the leader's for it to be promoted.
+    pub fn is_caught_up(&self, learner: &str) -> bool {
+        let matched = self.match_index.get(learner).copied().unwrap_or(0);
+        matched + self.promotion_lag >= self.last_log_index()
+    }
+
+    /// Learner to promote, one whose log caught up with the leader's since it has been heard from
+    /// in this term, if any.
+    pub fn caught_up_learner(&self) -> Option<String> {
+        self.learners
+            .iter()
+            .find(|learner| {
+                self.match_index
+                    .get(*learner)
+                    .is_some_and(|matched| *matched > 0)
+                    && self.is_caught_up(learner)
+            })
+            .cloned()
+    }
 
+    /// Checks if log holds a configuration entry that isn't committed yet, only one membership
+    /// change may be in progress at a time.
+    pub fn is_membership_changing(&self) -> bool {
+        self.log
+            .entries(self.commit_index + 1..self.last_log_index() + 1)
+            .iter()
+            .any(|entry| entry.kind == EntryKind::Config)
+    }
+
+    /// Follows node as Leader of term, which it has proven to be by sending an AppendEntries
+    /// or InstallSnapshot, no need to start an election.
+    pub fn follow(&mut self, term: u64, id: u64, addr: String) {
+        if term > self.current_term || self.role == NodeRole::Candidate {
+            self.step_down(term);
+        }
+        self.reset_election_timer();
+        self.leader_contact = self.clock.now();
+        self.leader_id = Some(id);
+        self.leader_addr = Some(addr);
+        self.publish();
+    }
+
+    /// Defers this node's next election by a full election timeout, for as long as it hears from
+    /// a valid leader or candidate.
+    pub fn reset_election_timer(&mut self) {
+        self.last_heartbeat = self.clock.now();
+        // Drawn at random so that nodes rarely campaign at once
+        let (min, max) = (self.election_timeout_min, self.election_timeout_max);
+        self.election_timeout = match min < max {
+            true => rand::thread_rng().gen_range(min..=max),
+            false => min,
+        };
+    }
+
+    /// Time at which the next of this node's timers is due, for `RaftNode::tick`. Leaders and
+    /// learners have no election timer.
+    pub fn next_tick(&self) -> Instant {
+        let next = (self.last_round + self.heartbeat_interval)
+            .min(self.last_quorum_check + self.election_timeout_min);
+        match self.role != NodeRole::Leader && self.standing == Standing::Voter {
+            true => next.min(self.last_heartbeat + self.election_timeout),
+            false => next,
+        }
+    }
+
+    /// Checks if this node knows of a live leader, that is leads itself or heard from the leader
+    /// within the shortest election timeout.
+    pub fn has_leader(&self) -> bool {
+        self.role == NodeRole::Leader
+            || (self.leader_id.is_some()
+                && self.clock.elapsed(self.last_heartbeat) < self.election_timeout_min)
+    }
+
+    /// Current state of the node.
+    pub fn status(&self) -> RaftStatus {
+        RaftStatus {
+            id: self.id,
+            role: self.role,
+            standing: self.standing,
+            current_term: self.current_term,
+            commit_index: self.commit_index,
+            last_applied: self.last_applied,
+            leader: self.leader_addr.clone(),
+            peers: self.cluster.clone(),
+            learners: self.learners.clone(),
+            failures: self
+                .backoff
+                .iter()
+                .map(|(node, backoff)| (node.clone(), backoff.failures))
+                .collect(),
+        }
+    }
+
+    /// Measurements of the node, copied out like `status`.
+    pub fn metrics(&self) -> RaftMetrics {
+        let last_log_index = self.last_log_index();
+        let peers = match self.role {
+            NodeRole::Leader => self
+                .match_index
+                .iter()
+                .map(|(node, &match_index)| {
+                    let lag = last_log_index.saturating_sub(match_index);
+                    (node.clone(), PeerMetrics { match_index, lag })
+                })
+                .collect(),
+            _ => HashMap::new(),
+        };
+        RaftMetrics {
+            id: self.id,
+            role: self.role,
+            current_term: self.current_term,
+            leader_id: self.leader_id,
+            commit_index: self.commit_index,
+            last_applied: self.last_applied,
+            log_length: (last_log_index + 1).saturating_sub(self.log.first_index()),
+            snapshot_index: self.snapshot.index,
+            peers,
+            elections_started: self.elections_started,
+            elections_won: self.elections_won,
+            heartbeats_sent: self.heartbeats_sent,
+        }
+    }
+
+    /// Records that the node at addr goes by id, failing with `DuplicateId` if this node or another
+    /// member already does, as votes of both would be counted as one. Ids of nodes that left the
+    /// cluster may be taken again.
+    pub fn claim_id(&mut self, id: u64, addr: &str) -> Result<(), RaftError> {
+        let taken_by = if id == self.id && addr != self.addr {
+            Some(self.addr.clone())
+        } else {
+            let membership = self.membership();
+            self.ids
+                .get(&id)
+                .filter(|node| *node != addr && membership.contains(node))
+                .cloned()
+        };
+        if let Some(taken_by) = taken_by {
+            let e = RaftError::DuplicateId {
+                id,
+                addr: addr.to_string(),
+                taken_by,
+            };
+            error!(error = %e, "duplicate node id");
+            return Err(e);
+        }
+
+        // Node may have restarted under another id
+        self.ids.retain(|_, node| node != addr);
+        self.ids.insert(id, addr.to_string());
         Ok(())
     }
+
+    /// Checks if node is still to be left alone after RPCs failed to reach it.
+    pub fn is_backing_off(&self, node: &str) -> bool {
+        let now = self.clock.now();
+        self.backoff
+            .get(node)
+            .is_some_and(|backoff| now < backoff.retry_at)
+    }
+
+    /// Records that an RPC failed to reach node, which is left alone for twice as long as after
+    /// the previous failure, upto the longest backoff. Jitter keeps nodes from retrying in step.
+    pub fn record_failure(&mut self, node: &str) {
+        let (min, max) = self.retry_backoff;
+        let failures = self.backoff.get(node).map_or(0, |b| b.failures) + 1;
+        let backoff = min
+            .saturating_mul(1 << (failures - 1).min(16))
+            .min(max)
+            .mul_f64(rand::thread_rng().gen_range(0.5..=1.0));
+        debug!(node, failures, ?backoff, "node unreachable, backing off");
+        self.backoff.insert(
+            node.to_string(),
+            Backoff {
+                failures,
+                retry_at: self.clock.now() + backoff,
+            },
+        );
+    }
+
+    /// Records that an RPC reached node, which is sent RPCs as usual from then on.
+    pub fn record_success(&mut self, node: &str) {
+        if self.backoff.remove(node).is_some() {
+            debug!(node, "node reachable again");
+        }
+    }
+
+    /// Publishes the current status to subscribers, if role, term or leader changed since they
+    /// were last notified, along with whether the node is serving.
+    pub fn publish(&mut self) {
+        self.check_health();
+        let status = self.status();
+        let mut event = None;
+        self.events.send_if_modified(|current| {
+            let changed = current.role != status.role
+                || current.current_term != status.current_term
+                || current.leader != status.leader;
+            event = match status.role {
+                NodeRole::Leader if current.role != NodeRole::Leader => {
+                    Some(RaftEvent::BecameLeader {
+                        term: status.current_term,
+                    })
+                }
+                NodeRole::Candidate if changed => Some(RaftEvent::BecameCandidate {
+                    term: status.current_term,
+                }),
+                NodeRole::Follower
+                    if current.role != NodeRole::Follower || current.leader != status.leader =>
+                {
+                    Some(RaftEvent::BecameFollower {
+                        leader: status.leader.clone(),
+                    })
+                }
+                _ => None,
+            };
+            *current = status;
+            changed
+        });
+        if let Some(event) = event {
+            info!(
+                node_id = self.id,
+                term = self.current_term,
+                ?event,
+                "role changed"
+            );
+            self.emit(event);
+        }
+    }
+
+    /// Updates whether the node is serving, that is leads or heard from a leader within the health
+    /// timeout, notifying the health service if that changed.
+    pub fn check_health(&mut self) {
+        if self.role == NodeRole::Leader {
+            self.leader_contact = self.clock.now();
+        }
+        let serving = self.clock.elapsed(self.leader_contact) < self.health_timeout;
+        self.serving.send_if_modified(|current| {
+            let changed = *current != serving;
+            if changed {
+                info!(node_id = self.id, serving, "health changed");
+            }
+            *current = serving;
+            changed
+        });
+    }
+
+    /// Sends event to receivers of `RaftNode::events`, if there are any.
+    pub fn emit(&self, event: RaftEvent) {
+        let _ = self.observers.send(event);
+    }
+
+    /// Current role of the node within the cluster.
+    pub fn role(&self) -> NodeRole {
+        self.role
+    }
+
+    /// Adopt a term newer than the one currently known, stepping down to Follower.
+    pub fn step_down(&mut self, term: u64) {
+        if term > self.current_term {
+            // Votes are cast once per term and leaders lead one, a new term clears both
+            self.voted_for = None;
+            self.leader_id = None;
+            self.leader_addr = None;
+        }
+        self.current_term = term;
+        self.role = NodeRole::Follower;
+        self.transferee = None;
+        self.publish();
+    }
+
+    /// Adopts term carried by a reply if it is newer, stepping down to Follower so that a stale
+    /// Leader or Candidate stops acting. Returns true if the node stepped down.
+    pub fn observe_term(&mut self, term: u64) -> io::Result<bool> {
+        if term <= self.current_term {
+            return Ok(false);
+        }
+        self.step_down(term);
+        self.persist()?;
+
+        Ok(true)
+    }
+
+    /// Advances commit index, resolving proposals waiting on entries upto index. Committed
+    /// configuration entries change the membership of the cluster. Commit index is persisted
+    /// before anything acts on it, so that a restarted node applies the same entries again.
+    pub fn commit(&mut self, index: u64) -> io::Result<()> {
+        if index <= self.commit_index {
+            return Ok(());
+        }
+        let previous = std::mem::replace(&mut self.commit_index, index);
+        if let Err(e) = self.persist() {
+            self.commit_index = previous;
+            return Err(e);
+        }
+
+        let config = self
+            .log
+            .entries(previous + 1..index + 1)
+            .into_iter()
+            .rev()
+            .find(|entry| entry.kind == EntryKind::Config);
+        if let Some(membership) = config.and_then(|entry| Membership::from_bytes(&entry.data).ok())
+        {
+            self.apply_membership(membership);
+        }
+        self.committed.notify_one();
+
+        let waiting = self.pending.split_off(&(index + 1));
+        for (index, proposal) in std::mem::replace(&mut self.pending, waiting) {
+            let _ = proposal.send(index);
+        }
+
+        Ok(())
+    }
+
+    /// Records that the state machine applied all entries upto index, waking those waiting on it.
+    pub fn set_last_applied(&mut self, index: u64) {
+        self.last_applied = index;
+        self.applied.send_replace(index);
+    }
+
+    /// Index of the last entry in log, 0 if empty.
+    pub fn last_log_index(&self) -> u64 {
+        self.log.last_index()
+    }
+
+    /// Term of the last entry in log, 0 if empty.
+    pub fn last_log_term(&self) -> u64 {
+        self.log.term(self.last_log_index()).unwrap_or_default()
+    }
+
+    /// Checks if a candidate's log is at least as up-to-date as the local log, comparing the
+    /// term of the last entries first and the length of the logs if they end with the same term.
+    pub fn is_log_up_to_date(&self, last_log_index: u64, last_log_term: u64) -> bool {
+        let local_term = self.last_log_term();
+        last_log_term > local_term
+            || (last_log_term == local_term && last_log_index >= self.last_log_index())
+    }
+
+    /// Term of the entry at given index in log, None if there is no such entry.
+    pub fn term_at(&self, index: u64) -> Option<u64> {
+        self.log.term(index)
+    }
+
+    /// Appends entries sent by the leader after `prev_log_index`, returns false if the local log
+    /// doesn't contain an entry at `prev_log_index` with term `prev_log_term`. Any local entry that
+    /// conflicts with a new one, having the same index but a different term, is removed along with
+    /// all that follow it.
+    pub fn append_log(
+        &mut self,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        mut entries: Vec<LogEntry>,
+    ) -> io::Result<bool> {
+        if prev_log_index < self.snapshot.index {
+            // Entries upto the snapshot are committed, so they match those of the leader
+            entries.retain(|entry| entry.index > self.snapshot.index);
+        } else if self.term_at(prev_log_index) != Some(prev_log_term) {
+            return Ok(false);
+        }
+
+        let mut new_entries = vec![];
+        for entry in entries {
+            match self.term_at(entry.index) {
+                // Entry already exists in log
+                Some(term) if term == entry.term => continue,
+                // Conflicting entry, truncate log from here on
+                Some(_) => self.truncate_log(entry.index)?,
+                None => {}
+            }
+            // Entries must be contiguous, anything beyond a gap can't be stored
+            if entry.index != self.last_log_index() + new_entries.len() as u64 + 1 {
+                break;
+            }
+            new_entries.push(entry);
+        }
+        self.log.append(&new_entries)?;
+
+        Ok(true)
+    }
+
+    /// Removes entries from index onwards, along with proposals waiting on them.
+    fn truncate_log(&mut self, index: u64) -> io::Result<()> {
+        self.log.truncate_from(index)?;
+        // Dropping senders notifies proposers that their entries won't be committed
+        self.pending.split_off(&index);
+
+        Ok(())
+    }
+
+    /// Appends data as an entry of the current term to the leader's log, returning its index.
+    pub fn append_entry(&mut self, kind: EntryKind, data: Vec<u8>) -> io::Result<u64> {
+        let index = self.last_log_index() + 1;
+        self.log.append(&[LogEntry {
+            index,
+            term: self.current_term,
+            kind,
+            data,
+        }])?;
+
+        Ok(index)
+    }
+
+    /// Takes over as Leader of the current term, assuming every node's log matches its own
+    /// until AppendEntries replies say otherwise.
+    pub fn become_leader(&mut self) {
+        self.role = NodeRole::Leader;
+        self.elections_won += 1;
+        self.leader_id = Some(self.id);
+        self.leader_addr = Some(self.addr.clone());
+
+        // Every node gets an election timeout to acknowledge the new leader
+        let next_index = self.last_log_index() + 1;
+        self.next_index.clear();
+        self.match_index.clear();
+        self.last_ack.clear();
+        self.lease = None;
+        for node in self.peers() {
+            self.next_index.insert(node.clone(), next_index);
+            self.match_index.insert(node.clone(), 0);
+            self.last_ack.insert(node, self.clock.now());
+        }
+        self.publish();
+    }
+
+    /// AppendEntries for node, carrying entries from its next index onwards, upto
+    /// `max_append_entries` of them. Empty for nodes that are up to date, acting as a heartbeat.
+    /// Compacted entries can't be sent anymore, nodes that still need them are sent entries
+    /// following the snapshot, which they reject until they install the snapshot.
+    pub fn append_request(&self, node: &str) -> EntryRequest {
+        let next_index = self
+            .next_index
+            .get(node)
+            .copied()
+            .unwrap_or(1)
+            .max(self.log.first_index());
+        let prev_log_index = next_index - 1;
+        // Followers further behind get the rest in later rounds
+        let end =
+            (self.last_log_index() + 1).min(next_index + self.max_append_entries.max(1) as u64);
+        EntryRequest {
+            term: self.current_term,
+            id: self.id,
+            prev_log_index,
+            prev_log_term: self.term_at(prev_log_index).unwrap_or_default(),
+            entries: self
+                .log
+                .entries(next_index..end)
+                .into_iter()
+                .map(Entry::from)
+                .collect(),
+            commit_index: self.commit_index,
+            leader_addr: self.addr.clone(),
+        }
+    }
+
+    /// Where the local log diverges from the leader's, given that it has no entry at
+    /// `prev_log_index` matching the leader's. Returns index of the first entry of the
+    /// conflicting term along with that term, or the index following the end of log and term 0
+    /// if the log is shorter, so the leader can skip past a whole term at once.
+    pub fn conflict(&self, prev_log_index: u64) -> (u64, u64) {
+        let term = match self.term_at(prev_log_index) {
+            Some(term) => term,
+            None => return (self.last_log_index() + 1, 0),
+        };
+        let mut index = prev_log_index;
+        while index > self.log.first_index() && self.term_at(index - 1) == Some(term) {
+            index -= 1;
+        }
+        (index, term)
+    }
+
+    /// Updates replication progress of node from its reply to an AppendEntries. When its log
+    /// didn't match, next index moves back past the conflicting term the node reported, to the
+    /// leader's last entry of that term if it has any, so that logs far apart converge in a
+    /// round trip per term rather than per entry. Steps down if node is on a newer term.
+    pub fn handle_append_reply(
+        &mut self,
+        node: &str,
+        request: &EntryRequest,
+        reply: &EntryReply,
+    ) -> io::Result<()> {
+        if self.observe_term(reply.term)?
+            || self.role != NodeRole::Leader
+            || request.term != self.current_term
+        {
+            return Ok(());
+        }
+        self.last_ack.insert(node.to_string(), self.clock.now());
+
+        if reply.success {
+            let matched = request.prev_log_index + request.entries.len() as u64;
+            let match_index = self.match_index.entry(node.to_string()).or_insert(0);
+            *match_index = (*match_index).max(matched);
+            self.next_index.insert(node.to_string(), *match_index + 1);
+            self.advance_commit_index()?;
+        } else {
+            // Replies without a hint, as from nodes that predate them, move back a single entry
+            let mut next_index = match reply.conflict_index {
+                0 => request.prev_log_index,
+                index => index,
+            };
+            if reply.conflict_term > 0 {
+                // Terms only grow along the log, so the leader's last entry of the conflicting
+                // term is found by walking back over newer terms
+                let mut index = request.prev_log_index.min(self.last_log_index());
+                while index >= self.log.first_index()
+                    && self
+                        .term_at(index)
+                        .is_some_and(|term| term > reply.conflict_term)
+                {
+                    index -= 1;
+                }
+                if self.term_at(index) == Some(reply.conflict_term) {
+                    next_index = index + 1;
+                }
+            }
+            // Always move back, even on a hint pointing past the entries sent
+            let next_index = next_index.min(request.prev_log_index).max(1);
+            self.next_index.insert(node.to_string(), next_index);
+        }
+
+        Ok(())
+    }
+
+    /// Commits upto the highest index stored on a majority of the voting members, including the
+    /// leader unless it was removed, as long as that entry is from the current term. Entries from
+    /// previous terms are never committed by counting replicas, as a future leader could still
+    /// overwrite them, but get committed along with the first entry of the current term that
+    /// follows them.
+    pub fn advance_commit_index(&mut self) -> io::Result<()> {
+        let mut matched: Vec<u64> = self
+            .cluster
+            .iter()
+            .map(|node| self.match_index.get(node).copied().unwrap_or(0))
+            .collect();
+        if self.own_vote() > 0 {
+            matched.push(self.last_log_index());
+        }
+        if matched.is_empty() {
+            return Ok(());
+        }
+        matched.sort_unstable_by(|a, b| b.cmp(a));
+
+        let index = matched[self.quorum() - 1];
+        if index > self.commit_index && self.term_at(index) == Some(self.current_term) {
+            self.commit(index)?;
+        }
+
+        Ok(())
+    }
+
+    /// Number of votes needed to win an election, a majority of the voting members of the
+    /// cluster, including self unless it was removed.
+    pub fn quorum(&self) -> usize {
+        let voters = self.cluster.len() + self.own_vote();
+        voters / 2 + 1
+    }
+
+    /// Vote of this node towards a majority, none once it no longer is a voting member.
+    fn own_vote(&self) -> usize {
+        match self.standing {
+            Standing::Voter => 1,
+            _ => 0,
+        }
+    }
+
+    fn vote_request(&self, term: u64, pre_vote: bool) -> VoteRequest {
+        VoteRequest {
+            term,
+            candidate_id: self.id,
+            last_log_index: self.last_log_index(),
+            last_log_term: self.last_log_term(),
+            candidate_addr: self.addr.clone(),
+            pre_vote,
+        }
+    }
+}
+
+/// Sends a round of AppendEntries and checks if a majority of voting members, counting itself,
+/// acknowledged this node as leader of the current term meanwhile. Once it has, no other leader
+/// could have committed entries that this one doesn't know of.
+pub async fn confirm_leadership(details: &Arc<Mutex<RaftDetails>>) -> Result<bool, RaftError> {
+    let round = details.lock().await.clock.now();
+    replicate(details).await?;
+
+    let details = details.lock().await;
+    Ok(details.role == NodeRole::Leader && details.is_acked_since(round))
+}
+
+/// Sends the latest snapshot to node in chunks, for nodes that need entries which were compacted.
+/// Details are only locked between chunks, giving up if this node stops leading the term meanwhile.
+pub async fn send_snapshot(details: &Mutex<RaftDetails>, node: &str) -> Result<(), RaftError> {
+    let (transport, clock, timeout, snapshot, chunk_size, term, id, addr) = {
+        let details = details.lock().await;
+        (
+            details.transport.clone(),
+            details.clock.clone(),
+            details.rpc_timeout,
+            details.snapshot.clone(),
+            details.snapshot_chunk_size.max(1),
+            details.current_term,
+            details.id,
+            details.addr.clone(),
+        )
+    };
+    let mut offset = 0;
+    loop {
+        let end = (offset + chunk_size).min(snapshot.data.len());
+        let meta = raft_proto::SnapshotMeta::from(snapshot.meta());
+        let request = SnapshotRequest {
+            term,
+            id,
+            last_included_index: meta.index,
+            last_included_term: meta.term,
+            offset: offset as u64,
+            data: snapshot.data[offset..end].to_vec(),
+            done: end == snapshot.data.len(),
+            leader_addr: addr.clone(),
+            membership: meta.membership,
+        };
+        let done = request.done;
+        let rpc = transport.install_snapshot(node, Request::new(request));
+        let reply = within(&*clock, timeout, rpc).await?.into_inner();
+
+        let mut details = details.lock().await;
+        if details.observe_term(reply.term)?
+            || details.role != NodeRole::Leader
+            || details.current_term != term
+        {
+            return Ok(());
+        }
+        details.claim_id(reply.id, node)?;
+        details.last_ack.insert(node.to_string(), clock.now());
+        if done {
+            // Node's log now matches the leader's upto the snapshot
+            let match_index = details.match_index.entry(node.to_string()).or_insert(0);
+            *match_index = (*match_index).max(snapshot.index);
+            let next_index = *match_index + 1;
+            details.next_index.insert(node.to_string(), next_index);
+            details.advance_commit_index()?;
+            return Ok(());
+        }
+        offset = end;
+    }
+}
+
+/// Replicates log to all nodes in cluster and learners, sending each node entries it is missing,
+/// and commits entries once a majority of the voting members has stored them. Nodes missing entries
+/// that were compacted are sent the latest snapshot on a task of its own instead, nodes backing
+/// off nothing at all. Details are locked to send the requests and then for each reply, never
+/// while waiting on the network, and replies only count towards the term the round started in.
+#[instrument(level = "debug", skip_all, fields(node_id = field::Empty, term = field::Empty))]
+pub async fn replicate(details: &Arc<Mutex<RaftDetails>>) -> Result<(), RaftError> {
+    notify_departed(details).await;
+
+    let (voter, term, round, mut received) = {
+        let mut state = details.lock().await;
+        Span::current()
+            .record("node_id", state.id)
+            .record("term", state.current_term);
+        if state.role != NodeRole::Leader {
+            return Ok(());
+        }
+        // Removal of the leader itself was committed in an earlier round, which let the
+        // remaining members learn of it, so it can stop leading after this last one
+        let voter = state.standing == Standing::Voter;
+        // Replies only prove a majority heard from the leader since the round started
+        let round = state.clock.now();
+        // Every entry appended so far goes out with this round
+        state.batched = 0;
+
+        let (replies, received) = mpsc::unbounded_channel();
+        let mut sent = 0;
+        for node in state.peers() {
+            if state.is_backing_off(&node) || state.snapshotting.contains(&node) {
+                continue;
+            }
+            let next_index = state.next_index.get(&node).copied().unwrap_or(1);
+            if next_index < state.log.first_index() {
+                state.snapshotting.insert(node.clone());
+                let details = details.clone();
+                tokio::spawn(
+                    async move {
+                        let sent = send_snapshot(&details, &node).await;
+                        let mut details = details.lock().await;
+                        details.snapshotting.remove(&node);
+                        match sent {
+                            Ok(()) => details.record_success(&node),
+                            Err(_) => details.record_failure(&node),
+                        }
+                    }
+                    .in_current_span(),
+                );
+                continue;
+            }
+
+            // Requests to all nodes are in flight at once, so a node that is slow to fail doesn't
+            // hold up those to the others. Requests outlive the round if it is cut short
+            let request = state.append_request(&node);
+            let (transport, replies) = (state.transport.clone(), replies.clone());
+            let (clock, timeout) = (state.clock.clone(), state.rpc_timeout);
+            tokio::spawn(
+                async move {
+                    let rpc = transport.append_entries(&node, Request::new(request.clone()));
+                    let reply = within(&*clock, timeout, rpc).await;
+                    let _ = replies.send((node, request, reply));
+                }
+                .in_current_span(),
+            );
+            state.heartbeats_sent += 1;
+            sent += 1;
+        }
+        debug!(peers = sent, "sent heartbeats");
+        (voter, state.current_term, round, received)
+    };
+
+    // Replies are handled as they arrive, entries commit once the fastest majority stored them
+    while let Some((node, request, reply)) = received.recv().await {
+        let mut details = details.lock().await;
+        let reply = match reply {
+            Ok(reply) => reply.into_inner(),
+            Err(_) => {
+                details.record_failure(&node);
+                continue;
+            }
+        };
+        details.record_success(&node);
+        // Replies of nodes claiming a taken id are ignored
+        if details.claim_id(reply.id, &node).is_err() {
+            continue;
+        }
+
+        details.handle_append_reply(&node, &request, &reply)?;
+        if details.role != NodeRole::Leader || details.current_term != term {
+            return Ok(());
+        }
+    }
+
+    let mut details = details.lock().await;
+    if details.role != NodeRole::Leader || details.current_term != term {
+        return Ok(());
+    }
+    // Leader alone is a majority in a single node cluster
+    details.advance_commit_index()?;
+    // Transferee may be elected without waiting out its election timeout
+    if details.transferee.is_none() && details.is_acked_since(round) {
+        details.lease = details.lease_duration.map(|duration| round + duration);
+    }
+    if !voter {
+        details.role = NodeRole::Follower;
+        details.leader_id = None;
+        details.leader_addr = None;
+        details.publish();
+    }
+
+    Ok(())
+}
+
+/// Sends nodes that were removed from the cluster a last AppendEntries, carrying the commit of
+/// their removal, so that they stop campaigning. Nodes that miss it are kept from disrupting
+/// the cluster as members ignore their vote requests while the leader is live.
+async fn notify_departed(details: &Mutex<RaftDetails>) {
+    let (transport, clock, timeout, requests) = {
+        let mut details = details.lock().await;
+        let requests: Vec<_> = std::mem::take(&mut details.departed)
+            .into_iter()
+            .map(|node| {
+                let request = details.append_request(&node);
+                (node, request)
+            })
+            .collect();
+        if requests.is_empty() {
+            return;
+        }
+        (
+            details.transport.clone(),
+            details.clock.clone(),
+            details.rpc_timeout,
+            requests,
+        )
+    };
+    // Departed nodes are told at once, so that one that is unreachable doesn't hold up the others
+    let (notified, mut received) = mpsc::unbounded_channel();
+    for (node, request) in requests {
+        let (transport, clock, notified) = (transport.clone(), clock.clone(), notified.clone());
+        tokio::spawn(
+            async move {
+                let rpc = transport.append_entries(&node, Request::new(request));
+                let _ = within(&*clock, timeout, rpc).await;
+                transport.forget(&node);
+                let _ = notified.send(node);
+            }
+            .in_current_span(),
+        );
+    }
+    drop(notified);
+
+    while let Some(node) = received.recv().await {
+        let mut details = details.lock().await;
+        details.next_index.remove(&node);
+        details.match_index.remove(&node);
+    }
+}
+
+/// Tells node to campaign right away, without waiting for its election timeout, as the last
+/// step of transferring leadership to it.
+pub async fn timeout_now(details: &Mutex<RaftDetails>, node: &str) -> Result<(), RaftError> {
+    let (transport, clock, timeout, request) = {
+        let details = details.lock().await;
+        let request = TimeoutRequest {
+            term: details.current_term,
+            id: details.id,
+        };
+        (
+            details.transport.clone(),
+            details.clock.clone(),
+            details.rpc_timeout,
+            request,
+        )
+    };
+    let rpc = transport.timeout_now(node, Request::new(request));
+    let reply = within(&*clock, timeout, rpc).await?.into_inner();
+    details.lock().await.observe_term(reply.term)?;
+
+    Ok(())
+}
+
+/// Campaigns to become Leader for the next term, after a pre-vote if enabled, returns true if
+/// a majority granted its vote. Heartbeats and votes are still answered while waiting on the
+/// pre-vote, and a leader or candidate heard from meanwhile calls off the campaign.
+#[instrument(skip_all, fields(node_id = field::Empty, term = field::Empty))]
+pub async fn start_election(details: &Mutex<RaftDetails>) -> Result<bool, RaftError> {
+    let (pre_vote, started) = {
+        let mut details = details.lock().await;
+        Span::current()
+            .record("node_id", details.id)
+            .record("term", details.current_term);
+        // Election times out from when it started, not when the last vote arrived
+        details.reset_election_timer();
+        (details.pre_vote, details.last_heartbeat)
+    };
+    if pre_vote {
+        if !hold_pre_vote(details).await? {
+            debug!("pre-vote lost, not campaigning");
+            return Ok(false);
+        } else if details.lock().await.last_heartbeat != started {
+            debug!("heard from another node during pre-vote, not campaigning");
+            return Ok(false);
+        }
+    }
+    campaign(details).await
+}
+
+/// Campaigns to become Leader for the next term right away, skipping any pre-vote, returns
+/// true if a majority granted its vote. Votes only count if the node is still a candidate of
+/// that term once they arrived.
+#[instrument(skip_all, fields(node_id = field::Empty, term = field::Empty))]
+pub async fn campaign(details: &Mutex<RaftDetails>) -> Result<bool, RaftError> {
+    let (term, request) = {
+        let mut details = details.lock().await;
+        Span::current()
+            .record("node_id", details.id)
+            .record("term", details.current_term + 1);
+        info!("campaigning");
+        details.reset_election_timer();
+        details.current_term += 1;
+        details.role = NodeRole::Candidate;
+        details.leader_id = None;
+        details.leader_addr = None;
+        details.voted_for = Some(details.id);
+        details.elections_started += 1;
+        details.votes_recieved.clear();
+        let id = details.id;
+        details.votes_recieved.insert(id, true);
+        details.persist()?;
+        details.publish();
+        let term = details.current_term;
+        (term, details.vote_request(term, false))
+    };
+
+    let votes = request_votes(details, request).await?;
+    let mut details = details.lock().await;
+    let votes = match votes {
+        Some(votes) if details.current_term == term && details.role == NodeRole::Candidate => votes,
+        _ => {
+            info!(role = ?details.role, "election abandoned for a newer term");
+            return Ok(false);
+        }
+    };
+    details.votes_recieved.extend(votes);
+
+    let votes = details
+        .votes_recieved
+        .values()
+        .filter(|grant| **grant)
+        .count();
+    if votes >= details.quorum() {
+        details.become_leader();
+        info!(votes, "won election");
+        return Ok(true);
+    }
+
+    info!(votes, "lost election");
+    Ok(false)
+}
+
+/// Asks the cluster whether it would vote for this node in the next term, returns true if a
+/// majority would. Neither this node nor the voters change any state, so a node that can't
+/// win doesn't move the cluster to a newer term.
+async fn hold_pre_vote(details: &Mutex<RaftDetails>) -> Result<bool, RaftError> {
+    let request = {
+        let details = details.lock().await;
+        details.vote_request(details.current_term + 1, true)
+    };
+    let votes = match request_votes(details, request).await? {
+        Some(votes) => votes,
+        None => return Ok(false),
+    };
+
+    let granted = votes.values().filter(|grant| **grant).count();
+    Ok(granted + 1 >= details.lock().await.quorum())
+}
+
+/// Sends request to all voting members at once, but those backing off, returning whether each
+/// granted its vote, or None if a reply carried a newer term. Replies are tallied as they arrive,
+/// returning as soon as a majority granted, counting the candidate's own vote, or once the
+/// shortest election timeout passed. Details are only locked to send the requests and to handle
+/// each reply.
+async fn request_votes(
+    details: &Mutex<RaftDetails>,
+    request: VoteRequest,
+) -> Result<Option<HashMap<u64, bool>>, RaftError> {
+    let (clock, deadline, quorum, mut received) = {
+        let details = details.lock().await;
+        let (replies, received) = mpsc::unbounded_channel();
+        for node in details.cluster.clone() {
+            if details.is_backing_off(&node) {
+                continue;
+            }
+            let (transport, request) = (details.transport.clone(), request.clone());
+            let (clock, timeout, replies) =
+                (details.clock.clone(), details.rpc_timeout, replies.clone());
+            tokio::spawn(
+                async move {
+                    let rpc = transport.request_vote(&node, Request::new(request));
+                    let reply = within(&*clock, timeout, rpc).await;
+                    let _ = replies.send((node, reply));
+                }
+                .in_current_span(),
+            );
+        }
+        let deadline = details.clock.now() + details.election_timeout_min;
+        (details.clock.clone(), deadline, details.quorum(), received)
+    };
+
+    let mut votes = HashMap::new();
+    // Candidate already voted for itself
+    while votes.values().filter(|grant| **grant).count() + 1 < quorum {
+        let (node, reply) = tokio::select! {
+            reply = received.recv() => match reply {
+                Some(reply) => reply,
+                None => break,
+            },
+            _ = clock.sleep(deadline.saturating_duration_since(clock.now())) => {
+                debug!("election timed out waiting for votes");
+                break;
+            }
+        };
+        let mut details = details.lock().await;
+        // Unreachable nodes are simply counted as not having voted
+        let reply = match reply {
+            Ok(reply) => reply.into_inner(),
+            Err(_) => {
+                details.record_failure(&node);
+                continue;
+            }
+        };
+        details.record_success(&node);
+
+        // A newer term means some other node has moved the cluster forward
+        if details.observe_term(reply.term)? {
+            return Ok(None);
+        } else if details.claim_id(reply.id, &node).is_ok() {
+            votes.insert(reply.id, reply.grant);
+        }
+    }
+
+    Ok(Some(votes))
+}
+
+/// Awaits the reply to an RPC, failing with `Status::deadline_exceeded` unless it arrives within
+/// timeout on clock, so that a node which stopped responding can't stall the sender.
+async fn within<T>(
+    clock: &dyn Clock,
+    timeout: Duration,
+    rpc: impl Future<Output = Result<T, Status>>,
+) -> Result<T, Status> {
+    tokio::select! {
+        reply = rpc => reply,
+        _ = clock.sleep(timeout) => Err(Status::deadline_exceeded(format!(
+            "No reply within {:?}",
+            timeout
+        ))),
+    }
 }
@@ -1,4 +1,28 @@
-use std::{collections::HashMap, error::Error, fmt};
+use std::{
+    collections::HashMap,
+    convert::{TryFrom, TryInto},
+    error::Error,
+    fmt,
+};
+
+use crate::{error::DecodeError, raft::RaftData};
+
+/// Application logic replicated by raft, every node applies the same committed entries in the
+/// same order, exactly once, so that all state machines in the cluster end up in the same state.
+pub trait StateMachine: Send + 'static {
+    /// Commands proposed to the cluster, carried by log entries in their serialized form.
+    type Command: RaftData + Send;
+    /// Applies command of the committed entry at index, returning output of the operation.
+    fn apply(&mut self, index: u64, command: Self::Command) -> Vec<u8>;
+    /// Serializes current state of the state machine.
+    fn snapshot(&self) -> Vec<u8>;
+    /// Replaces current state with one serialized by `snapshot`. Fails on bytes that aren't such
+    /// a snapshot, leaving current state as it was.
+    fn restore(&mut self, snapshot: &[u8]) -> Result<(), DecodeError>;
+    /// Called in place of `apply` for the committed entry at index whose command couldn't be
+    /// decoded, which fails alike on every node. The entry counts as applied all the same.
+    fn decode_failed(&mut self, _index: u64, _error: DecodeError) {}
+}
 
 #[derive(Debug, Copy, Clone)]
 pub enum RaftTask {
@@ -44,12 +68,28 @@ impl RaftCommand {
     }
 }
 
-pub struct StateMachine {
+impl RaftData for RaftCommand {
+    fn as_bytes(&self) -> Vec<u8> {
+        RaftCommand::as_bytes(self)
+    }
+
+    fn try_from_bytes(bytes: Vec<u8>) -> Result<Self, DecodeError> {
+        // Task and node are packed into the first byte, which must be there
+        match bytes.is_empty() {
+            true => Err(DecodeError("empty command".to_string())),
+            false => Ok(RaftCommand::from_bytes(bytes)),
+        }
+    }
+}
+
+/// Default state machine, tracking which nodes of a compute cluster are executing which tasks.
+#[derive(Default)]
+pub struct RaftStateMachine {
     executing: HashMap<u8, Vec<u8>>,
     free_nodes: Vec<u8>,
 }
 
-impl StateMachine {
+impl RaftStateMachine {
     pub fn new() -> Self {
         Self {
             executing: HashMap::new(),
@@ -91,6 +131,60 @@ impl StateMachine {
     }
 }
 
+impl StateMachine for RaftStateMachine {
+    type Command = RaftCommand;
+
+    /// Runs a RaftCommand, outputs are empty on success and describe the error otherwise.
+    fn apply(&mut self, _: u64, command: RaftCommand) -> Vec<u8> {
+        // Commands that can't be scheduled are rejected the same on every node, leaving state as is
+        match self.run_cmd(command) {
+            Ok(()) => vec![],
+            Err(e) => e.to_string().into_bytes(),
+        }
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        // Free nodes prefixed by their count, followed by executing nodes with their payloads
+        let mut bytes = vec![self.free_nodes.len() as u8];
+        bytes.extend_from_slice(&self.free_nodes);
+        let mut nodes: Vec<_> = self.executing.keys().collect();
+        nodes.sort();
+        for node in nodes {
+            let data = &self.executing[node];
+            bytes.push(*node);
+            bytes.extend_from_slice(&(data.len() as u64).to_be_bytes());
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> Result<(), DecodeError> {
+        // Parsed in full before replacing anything, so a bad snapshot leaves state as it was
+        let truncated = || DecodeError("truncated snapshot".to_string());
+        let mut free_nodes = vec![];
+        let mut executing = HashMap::new();
+        if let Some((&free, mut rest)) = snapshot.split_first() {
+            let free = rest.get(..free as usize).ok_or_else(truncated)?;
+            free_nodes.extend_from_slice(free);
+            rest = &rest[free.len()..];
+            while let Some((&node, tail)) = rest.split_first() {
+                let len = tail.get(..8).ok_or_else(truncated)?;
+                let len = u64::from_be_bytes(len.try_into().unwrap());
+                let data = usize::try_from(len)
+                    .ok()
+                    .and_then(|len| tail[8..].get(..len))
+                    .ok_or_else(truncated)?;
+                executing.insert(node, data.to_vec());
+                rest = &tail[8 + data.len()..];
+            }
+        }
+
+        self.free_nodes = free_nodes;
+        self.executing = executing;
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct ScheduleError(String);
 
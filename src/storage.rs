@@ -0,0 +1,658 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{
+    convert::{TryFrom, TryInto},
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+use tracing::warn;
+
+use crate::{
+    error::RaftError,
+    raft::{EntryKind, LogEntry},
+    raft_proto,
+};
+
+/// State that must survive restarts for raft to stay safe, a node that forgets either term or vote
+/// could vote twice in a term or regress to an older term. Commit index is kept alongside, so that
+/// a restarted node knows which entries of its log are already committed.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct HardState {
+    pub current_term: u64,
+    pub voted_for: Option<u64>,
+    pub commit_index: u64,
+}
+
+/// State of the state machine as of index, replacing all entries of the log upto index.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Snapshot {
+    pub index: u64,
+    pub term: u64,
+    /// Membership of the cluster as of index, None if it is still the one the node started with
+    pub membership: Option<Membership>,
+    pub data: Vec<u8>,
+}
+
+impl Snapshot {
+    /// Which entries the snapshot replaces and the membership it holds, without its data.
+    pub fn meta(&self) -> SnapshotMeta {
+        SnapshotMeta {
+            index: self.index,
+            term: self.term,
+            membership: self.membership.clone(),
+        }
+    }
+}
+
+/// Everything of a snapshot but the state machine's data, which is stored ahead of the data.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SnapshotMeta {
+    pub index: u64,
+    pub term: u64,
+    /// Membership of the cluster as of index, None if it is still the one the node started with
+    pub membership: Option<Membership>,
+}
+
+impl SnapshotMeta {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        // Index and term of the last entry replaced and length prefixed membership, empty if
+        // there is none
+        let membership = self
+            .membership
+            .as_ref()
+            .map(Membership::as_bytes)
+            .unwrap_or_default();
+        let mut bytes = self.index.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.term.to_be_bytes());
+        bytes.extend_from_slice(&(membership.len() as u64).to_be_bytes());
+        bytes.extend_from_slice(&membership);
+        bytes
+    }
+
+    /// Reads metadata from the start of bytes, returning it along with the bytes that follow it.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<(Self, &[u8])> {
+        let membership_len = read_u64(bytes, 16)? as usize;
+        let end = 24usize.saturating_add(membership_len);
+        let membership = match bytes.get(24..end).ok_or_else(corrupt)? {
+            [] => None,
+            membership => Some(Membership::from_bytes(membership)?),
+        };
+        let meta = Self {
+            index: read_u64(bytes, 0)?,
+            term: read_u64(bytes, 8)?,
+            membership,
+        };
+
+        Ok((meta, &bytes[end..]))
+    }
+}
+
+impl From<SnapshotMeta> for raft_proto::SnapshotMeta {
+    fn from(meta: SnapshotMeta) -> Self {
+        Self {
+            index: meta.index,
+            term: meta.term,
+            membership: meta
+                .membership
+                .as_ref()
+                .map(Membership::as_bytes)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl TryFrom<raft_proto::SnapshotMeta> for SnapshotMeta {
+    type Error = io::Error;
+
+    /// Fails on membership that doesn't decode, none is read from empty membership.
+    fn try_from(meta: raft_proto::SnapshotMeta) -> io::Result<Self> {
+        let membership = match meta.membership.as_slice() {
+            [] => None,
+            membership => Some(Membership::from_bytes(membership)?),
+        };
+        Ok(Self {
+            index: meta.index,
+            term: meta.term,
+            membership,
+        })
+    }
+}
+
+/// Addresses of all members of the cluster, as replicated through configuration entries. Voting
+/// members elect the leader and make up the quorum, learners only receive the log.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Membership {
+    pub members: Vec<String>,
+    pub learners: Vec<String>,
+}
+
+impl Membership {
+    pub fn new(members: Vec<String>) -> Self {
+        Self {
+            members,
+            learners: vec![],
+        }
+    }
+
+    /// Checks if addr is either a voting member or a learner.
+    pub fn contains(&self, addr: &str) -> bool {
+        self.members
+            .iter()
+            .chain(self.learners.iter())
+            .any(|member| member == addr)
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        // Voting members followed by learners, each as a number of length prefixed addresses
+        let mut bytes = vec![];
+        for addrs in [&self.members, &self.learners].iter() {
+            bytes.extend_from_slice(&(addrs.len() as u64).to_be_bytes());
+            for addr in addrs.iter() {
+                bytes.extend_from_slice(&(addr.len() as u64).to_be_bytes());
+                bytes.extend_from_slice(addr.as_bytes());
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let mut at = 0;
+        let mut read_addrs = || {
+            let count = read_u64(bytes, at)?;
+            at += 8;
+            let mut addrs = vec![];
+            for _ in 0..count {
+                let len = read_u64(bytes, at)? as usize;
+                let addr = bytes.get(at + 8..at + 8 + len).ok_or_else(corrupt)?;
+                addrs.push(String::from_utf8(addr.to_vec()).map_err(|_| corrupt())?);
+                at += 8 + len;
+            }
+            Ok::<_, io::Error>(addrs)
+        };
+
+        Ok(Self {
+            members: read_addrs()?,
+            learners: read_addrs()?,
+        })
+    }
+}
+
+/// Durable storage for a node's hard state and latest snapshot. Writes must be flushed before
+/// returning, as replies to RPCs that changed state are only sent once `save` returns.
+pub trait Storage: Send {
+    /// Loads state persisted by a previous run, defaults if nothing was persisted.
+    fn load(&mut self) -> io::Result<HardState>;
+    /// Durably persists state, replacing whatever was persisted before.
+    fn save(&mut self, hard_state: &HardState) -> io::Result<()>;
+    /// Loads the latest snapshot persisted, None if there is none.
+    fn load_snapshot(&mut self) -> io::Result<Option<Snapshot>>;
+    /// Durably persists snapshot, replacing whichever was persisted before.
+    fn save_snapshot(&mut self, snapshot: &Snapshot) -> io::Result<()>;
+}
+
+/// Keeps hard state purely in memory, it is lost when the process exits.
+#[derive(Default)]
+pub struct MemStorage {
+    hard_state: HardState,
+    snapshot: Option<Snapshot>,
+}
+
+impl MemStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemStorage {
+    fn load(&mut self) -> io::Result<HardState> {
+        Ok(self.hard_state)
+    }
+
+    fn save(&mut self, hard_state: &HardState) -> io::Result<()> {
+        self.hard_state = *hard_state;
+        Ok(())
+    }
+
+    fn load_snapshot(&mut self) -> io::Result<Option<Snapshot>> {
+        Ok(self.snapshot.clone())
+    }
+
+    fn save_snapshot(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        self.snapshot = Some(snapshot.clone());
+        Ok(())
+    }
+}
+
+/// Persists hard state to a file, fsyncing every write. Snapshots are persisted to a file
+/// alongside, with the same name but a `snapshot` extension.
+pub struct FileStorage {
+    path: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+/// Leads hard state and snapshot files.
+const FORMAT_VERSION: u8 = 1;
+
+/// Contents of a hard state or snapshot file after its version, failing on any other version.
+fn versioned(bytes: &[u8]) -> io::Result<&[u8]> {
+    match bytes.split_first() {
+        Some((&FORMAT_VERSION, rest)) => Ok(rest),
+        _ => Err(corrupt()),
+    }
+}
+
+/// Writes bytes to a temporary file first and then replaces the file at path with it, so a
+/// crash mid write doesn't corrupt what was at path.
+fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(tmp, path)
+}
+
+impl Storage for FileStorage {
+    fn load(&mut self) -> io::Result<HardState> {
+        let bytes = match fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Default::default()),
+            Err(e) => return Err(e),
+        };
+        let bytes = versioned(&bytes)?;
+        let current_term = read_u64(bytes, 0)?;
+        let (voted_for, commit_index) = match bytes.len() {
+            25 => (
+                (bytes[8] == 1).then_some(read_u64(bytes, 9)?),
+                read_u64(bytes, 17)?,
+            ),
+            _ => return Err(corrupt()),
+        };
+
+        Ok(HardState {
+            current_term,
+            voted_for,
+            commit_index,
+        })
+    }
+
+    fn save(&mut self, hard_state: &HardState) -> io::Result<()> {
+        // Version, term, followed by a flag and id of the node voted for and the commit index
+        let mut bytes = vec![FORMAT_VERSION];
+        bytes.extend_from_slice(&hard_state.current_term.to_be_bytes());
+        match hard_state.voted_for {
+            Some(id) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&id.to_be_bytes());
+            }
+            None => bytes.extend_from_slice(&[0; 9]),
+        }
+        bytes.extend_from_slice(&hard_state.commit_index.to_be_bytes());
+
+        write_atomic(&self.path, &bytes)
+    }
+
+    fn load_snapshot(&mut self) -> io::Result<Option<Snapshot>> {
+        let bytes = match fs::read(self.path.with_extension("snapshot")) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let (meta, data) = SnapshotMeta::from_bytes(versioned(&bytes)?)?;
+
+        Ok(Some(Snapshot {
+            index: meta.index,
+            term: meta.term,
+            membership: meta.membership,
+            data: data.to_vec(),
+        }))
+    }
+
+    fn save_snapshot(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        // Version and metadata, followed by the state machine's data
+        let mut bytes = vec![FORMAT_VERSION];
+        bytes.extend_from_slice(&snapshot.meta().as_bytes());
+        bytes.extend_from_slice(&snapshot.data);
+
+        write_atomic(&self.path.with_extension("snapshot"), &bytes)
+    }
+}
+
+/// Storage of the replicated log, indexed from 1. Appended entries must be durable before
+/// returning, as followers acknowledge entries to the leader only once they are stored. Entries
+/// covered by a snapshot can be discarded from the start of log by compacting it.
+pub trait LogStore: Send {
+    /// Appends entries to the end of log.
+    fn append(&mut self, entries: &[LogEntry]) -> io::Result<()>;
+    /// Removes the entry at index and all that follow it.
+    fn truncate_from(&mut self, index: u64) -> io::Result<()>;
+    /// Discards all entries upto and including index, which a snapshot taken at index of term
+    /// replaces.
+    fn compact(&mut self, index: u64, term: u64) -> io::Result<()>;
+    /// Entries with indices within range, that are in log.
+    fn entries(&self, range: Range<u64>) -> Vec<LogEntry>;
+    /// Index of the first entry still in log, 1 more than the index of the latest snapshot.
+    fn first_index(&self) -> u64;
+    /// Index of the last entry in log, that of the latest snapshot if empty.
+    fn last_index(&self) -> u64;
+    /// Term of the entry at index, None if there is no such entry or it was compacted. Index of
+    /// the latest snapshot has its term, or 0 if there is none.
+    fn term(&self, index: u64) -> Option<u64>;
+
+    /// Entry at index, None if there is no such entry or it was compacted.
+    fn get(&self, index: u64) -> Option<LogEntry> {
+        self.entries(index..index + 1).pop()
+    }
+
+    /// Last entry in log, None if it is empty.
+    fn last(&self) -> Option<LogEntry> {
+        self.get(self.last_index())
+    }
+}
+
+/// Keeps the log purely in memory, it is lost when the process exits.
+#[derive(Default)]
+pub struct MemLog {
+    snapshot_index: u64,
+    snapshot_term: u64,
+    entries: Vec<LogEntry>,
+}
+
+impl MemLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Position of the entry at index within entries.
+    fn offset(&self, index: u64) -> usize {
+        index.saturating_sub(self.snapshot_index + 1) as usize
+    }
+}
+
+impl LogStore for MemLog {
+    fn append(&mut self, entries: &[LogEntry]) -> io::Result<()> {
+        self.entries.extend_from_slice(entries);
+        Ok(())
+    }
+
+    fn truncate_from(&mut self, index: u64) -> io::Result<()> {
+        let keep = self.offset(index);
+        self.entries.truncate(keep);
+        Ok(())
+    }
+
+    fn compact(&mut self, index: u64, term: u64) -> io::Result<()> {
+        if index <= self.snapshot_index {
+            return Ok(());
+        }
+        let discard = self.offset(index + 1).min(self.entries.len());
+        self.entries.drain(..discard);
+        self.snapshot_index = index;
+        self.snapshot_term = term;
+        Ok(())
+    }
+
+    fn entries(&self, range: Range<u64>) -> Vec<LogEntry> {
+        let end = self.offset(range.end).min(self.entries.len());
+        let start = self.offset(range.start).min(end);
+        self.entries[start..end].to_vec()
+    }
+
+    fn first_index(&self) -> u64 {
+        self.snapshot_index + 1
+    }
+
+    fn last_index(&self) -> u64 {
+        self.snapshot_index + self.entries.len() as u64
+    }
+
+    fn term(&self, index: u64) -> Option<u64> {
+        if index < self.snapshot_index {
+            None
+        } else if index == self.snapshot_index {
+            Some(self.snapshot_term)
+        } else {
+            self.entries.get(self.offset(index)).map(|entry| entry.term)
+        }
+    }
+}
+
+/// Starts log files, ahead of the snapshot they follow.
+const LOG_MAGIC: &[u8; 8] = b"RAFTLOG1";
+
+/// Persists the log to an append-only file, fsyncing every write. Entries are also cached
+/// in memory, so reads never touch the file.
+///
+/// Each entry is stored with a CRC32 of it, checked as the log is read on opening. Opening fails
+/// at the first entry that doesn't match, with an error that converts into
+/// `RaftError::LogCorruption`, rather than loading it or any entry after it. The last entry is the
+/// exception, a crash while it was written leaves it cut short or garbled, so it is dropped from
+/// the file instead, along with whatever else the write left behind. Only an entry that is neither
+/// committed nor followed by another valid entry counts as torn, anything else fails opening.
+pub struct FileLog {
+    path: PathBuf,
+    file: File,
+    cache: MemLog,
+    /// Offset in file at which each cached entry starts
+    offsets: Vec<u64>,
+    len: u64,
+}
+
+impl FileLog {
+    /// Opens the log persisted at path, creating an empty one if there is none. Entries upto
+    /// commit_index, as persisted with the node's hard state, must all be there.
+    pub fn open(path: PathBuf, commit_index: u64) -> io::Result<Self> {
+        let file = open_append(&path)?;
+        let bytes = fs::read(&path)?;
+
+        let mut log = Self {
+            path,
+            file,
+            cache: MemLog::new(),
+            offsets: vec![],
+            len: bytes.len() as u64,
+        };
+        if bytes.is_empty() {
+            log.write_header()?;
+            return Ok(log);
+        }
+
+        // Magic, then index and term of the snapshot the log starts after, followed by entries
+        // stored as term, kind and length prefixed data, then a checksum of all of these
+        if bytes.get(0..8) != Some(&LOG_MAGIC[..]) {
+            return Err(corrupt());
+        }
+        log.cache.snapshot_index = read_u64(&bytes, 8)?;
+        log.cache.snapshot_term = read_u64(&bytes, 16)?;
+        let mut at = 24;
+        while at < bytes.len() {
+            let index = log.cache.last_index() + 1;
+            // Entries that run past the end of the file, or end right at it but don't match their
+            // checksum, were being written when the node crashed. Entries before were synced
+            let len = match bytes.get(at + 9..at + 17) {
+                Some(len) => u64::from_be_bytes(len.try_into().unwrap()) as usize,
+                None => usize::MAX,
+            };
+            let end = (at + 17).saturating_add(len);
+            let next = end.saturating_add(4);
+            if next > bytes.len() {
+                // Length may be what is corrupt, in which case valid entries follow
+                if index <= commit_index || (at + 17..bytes.len()).any(|at| is_entry(&bytes, at)) {
+                    return Err(corruption(index));
+                }
+                log.truncate_torn(at, index)?;
+                break;
+            }
+            let data = &bytes[at + 17..end];
+            if bytes[end..next] != crc32fast::hash(&bytes[at..end]).to_be_bytes() {
+                if next < bytes.len() || index <= commit_index {
+                    return Err(corruption(index));
+                }
+                log.truncate_torn(at, index)?;
+                break;
+            }
+            let term = u64::from_be_bytes(bytes[at..at + 8].try_into().unwrap());
+            let kind = EntryKind::from_byte(bytes[at + 8]).ok_or_else(|| corruption(index))?;
+            log.offsets.push(at as u64);
+            log.cache.entries.push(LogEntry {
+                index,
+                term,
+                kind,
+                data: data.to_vec(),
+            });
+            at = next;
+        }
+
+        Ok(log)
+    }
+
+    /// Drops the entry at index, found at offset at of the file, which a crash left torn.
+    fn truncate_torn(&mut self, at: usize, index: u64) -> io::Result<()> {
+        warn!(index, "dropped log entry torn by a crash");
+        self.len = at as u64;
+        self.file.set_len(self.len)?;
+        self.file.sync_data()
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let mut bytes = LOG_MAGIC.to_vec();
+        bytes.extend_from_slice(&self.cache.snapshot_index.to_be_bytes());
+        bytes.extend_from_slice(&self.cache.snapshot_term.to_be_bytes());
+        self.file.write_all(&bytes)?;
+        self.file.sync_data()?;
+        self.len = bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Rewrites cached entries after a new header into a temporary file, replacing the log only
+    /// once it is complete so a crash mid write leaves the previous log intact.
+    fn rewrite(&mut self) -> io::Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        let _ = fs::remove_file(&tmp);
+        self.file = open_append(&tmp)?;
+        self.write_header()?;
+        self.offsets.clear();
+        let entries = self.cache.entries.clone();
+        let mut bytes = vec![];
+        for entry in entries.iter() {
+            self.offsets.push(self.len + bytes.len() as u64);
+            bytes.extend_from_slice(&encode_entry(entry));
+        }
+        self.file.write_all(&bytes)?;
+        self.file.sync_data()?;
+        self.len += bytes.len() as u64;
+        fs::rename(&tmp, &self.path)?;
+        self.file = open_append(&self.path)?;
+
+        Ok(())
+    }
+}
+
+impl LogStore for FileLog {
+    fn append(&mut self, entries: &[LogEntry]) -> io::Result<()> {
+        let mut bytes = vec![];
+        for entry in entries {
+            self.offsets.push(self.len + bytes.len() as u64);
+            bytes.extend_from_slice(&encode_entry(entry));
+        }
+        self.file.write_all(&bytes)?;
+        self.file.sync_data()?;
+        self.len += bytes.len() as u64;
+        self.cache.append(entries)
+    }
+
+    fn truncate_from(&mut self, index: u64) -> io::Result<()> {
+        let keep = self.cache.offset(index);
+        if keep >= self.offsets.len() {
+            return Ok(());
+        }
+        self.len = self.offsets[keep];
+        self.file.set_len(self.len)?;
+        self.file.sync_data()?;
+        self.offsets.truncate(keep);
+        self.cache.truncate_from(index)
+    }
+
+    fn compact(&mut self, index: u64, term: u64) -> io::Result<()> {
+        if index <= self.cache.snapshot_index {
+            return Ok(());
+        }
+        self.cache.compact(index, term)?;
+        self.rewrite()
+    }
+
+    fn entries(&self, range: Range<u64>) -> Vec<LogEntry> {
+        self.cache.entries(range)
+    }
+
+    fn first_index(&self) -> u64 {
+        self.cache.first_index()
+    }
+
+    fn last_index(&self) -> u64 {
+        self.cache.last_index()
+    }
+
+    fn term(&self, index: u64) -> Option<u64> {
+        self.cache.term(index)
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)
+}
+
+/// Whether bytes hold an entry starting at offset at, with a matching checksum.
+fn is_entry(bytes: &[u8], at: usize) -> bool {
+    let len = match bytes.get(at + 9..at + 17) {
+        Some(len) => u64::from_be_bytes(len.try_into().unwrap()) as usize,
+        None => return false,
+    };
+    let end = (at + 17).saturating_add(len);
+    match bytes.get(end..end.saturating_add(4)) {
+        Some(checksum) => checksum == crc32fast::hash(&bytes[at..end]).to_be_bytes(),
+        None => false,
+    }
+}
+
+fn encode_entry(entry: &LogEntry) -> Vec<u8> {
+    let mut bytes = entry.term.to_be_bytes().to_vec();
+    bytes.push(entry.kind.as_byte());
+    bytes.extend_from_slice(&(entry.data.len() as u64).to_be_bytes());
+    bytes.extend_from_slice(&entry.data);
+    let checksum = crc32fast::hash(&bytes);
+    bytes.extend_from_slice(&checksum.to_be_bytes());
+    bytes
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> io::Result<u64> {
+    let bytes = bytes.get(at..at + 8).ok_or_else(corrupt)?;
+    Ok(u64::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn corrupt() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "corrupt raft state")
+}
+
+/// Error of a log whose entry at index is corrupt, which converts into `RaftError::LogCorruption`.
+fn corruption(index: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        RaftError::LogCorruption { index },
+    )
+}
@@ -0,0 +1,251 @@
+//! Harness running a whole cluster within one process, for tests of consensus. Nodes talk over a
+//! `LocalTransport`, so that tests can partition them and drop messages between them, or drop,
+//! delay and reorder messages at random through `TestCluster::transport`.
+//!
+//! Run tests on a paused clock, as with `#[tokio::test(start_paused = true)]`, so that no timer
+//! fires before the cluster is idle. Time then jumps straight to the next timer, or as far as
+//! `TestCluster::advance` is told to, no matter how long the test actually takes.
+
+use std::{collections::HashSet, sync::Mutex};
+use tokio::time::{sleep, Duration, Instant};
+use tonic::Request;
+
+use crate::{
+    config::Config,
+    error::RaftError,
+    node::RaftNode,
+    raft::NodeRole,
+    raft_proto::TimeoutRequest,
+    state_machine::{RaftStateMachine, StateMachine},
+    transport::{LocalTransport, RaftTransport},
+};
+
+/// Cluster of nodes running within the process, addressed as `node-0`, `node-1` and so on.
+pub struct TestCluster<S: StateMachine = RaftStateMachine> {
+    transport: LocalTransport,
+    addrs: Vec<String>,
+    nodes: Vec<RaftNode<S>>,
+    /// Nodes cut off from the rest of the cluster
+    partitioned: Mutex<HashSet<usize>>,
+}
+
+impl TestCluster {
+    /// Starts a cluster of size nodes with the default state machine, running with config.
+    pub async fn new(size: usize, config: Config) -> Result<Self, RaftError> {
+        Self::with_state_machine(size, config, |_| RaftStateMachine::new()).await
+    }
+}
+
+impl<S: StateMachine> TestCluster<S> {
+    /// Starts a cluster of size nodes running with config, the node at each index applying
+    /// entries to the state machine returned for it.
+    pub async fn with_state_machine(
+        size: usize,
+        config: Config,
+        mut state_machine: impl FnMut(usize) -> S,
+    ) -> Result<Self, RaftError> {
+        let transport = LocalTransport::new();
+        let addrs: Vec<String> = (0..size).map(|i| format!("node-{}", i)).collect();
+        let mut nodes = vec![];
+        for (i, addr) in addrs.iter().enumerate() {
+            let node = RaftNode::builder(i as u64, addr.clone(), addrs.clone())
+                .state_machine(state_machine(i))
+                .transport(transport.bind(addr))
+                .start()
+                .await?;
+            transport.register(addr.clone(), node.clone());
+
+            let mut runner = node.clone();
+            let config = config.clone();
+            tokio::spawn(async move {
+                let _ = runner.run(config).await;
+            });
+            nodes.push(node);
+        }
+
+        Ok(Self {
+            transport,
+            addrs,
+            nodes,
+            partitioned: Mutex::new(HashSet::new()),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn node(&self, i: usize) -> &RaftNode<S> {
+        &self.nodes[i]
+    }
+
+    pub fn addr(&self, i: usize) -> &str {
+        &self.addrs[i]
+    }
+
+    /// Transport the nodes talk over, for finer control of what is delivered.
+    pub fn transport(&self) -> &LocalTransport {
+        &self.transport
+    }
+
+    /// Moves the paused clock forward by duration, stopping at each timer due meanwhile so that
+    /// the nodes act on it, as heartbeats due every interval are sent every interval.
+    pub async fn advance(&self, duration: Duration) {
+        sleep(duration).await;
+    }
+
+    /// Tells the node at i to campaign right away, as the leader does when transferring
+    /// leadership. Resolves once it leads, fails with `Timeout` if it doesn't within timeout.
+    pub async fn elect(&self, i: usize, timeout: Duration) -> Result<(), RaftError> {
+        let status = self.nodes[i].status().await;
+        let request = TimeoutRequest {
+            term: status.current_term,
+            id: status.id,
+        };
+        self.transport
+            .timeout_now(&self.addrs[i], Request::new(request))
+            .await?;
+
+        let deadline = Instant::now() + timeout;
+        while self.nodes[i].status().await.role != NodeRole::Leader {
+            if Instant::now() >= deadline {
+                return Err(RaftError::Timeout);
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        Ok(())
+    }
+
+    /// Cuts the node at i off from the rest of the cluster, until healed.
+    pub fn partition(&self, i: usize) {
+        self.transport.disconnect(&self.addrs[i]);
+        self.partitioned.lock().unwrap().insert(i);
+    }
+
+    /// Reconnects the node at i to the rest of the cluster.
+    pub fn heal(&self, i: usize) {
+        self.transport.reconnect(&self.addrs[i]);
+        self.partitioned.lock().unwrap().remove(&i);
+    }
+
+    /// Drops messages sent by the node at from to the node at to, until delivered again.
+    pub fn drop_messages(&self, from: usize, to: usize) {
+        self.transport.cut_link(&self.addrs[from], &self.addrs[to]);
+    }
+
+    /// Delivers messages sent by the node at from to the node at to again.
+    pub fn deliver_messages(&self, from: usize, to: usize) {
+        self.transport
+            .restore_link(&self.addrs[from], &self.addrs[to]);
+    }
+
+    /// Indices of the nodes that aren't partitioned.
+    pub fn connected(&self) -> Vec<usize> {
+        let partitioned = self.partitioned.lock().unwrap();
+        (0..self.len())
+            .filter(|i| !partitioned.contains(i))
+            .collect()
+    }
+
+    /// Indices of the nodes that believe they lead, along with their terms.
+    pub async fn leaders(&self) -> Vec<(usize, u64)> {
+        let mut leaders = vec![];
+        for (i, node) in self.nodes.iter().enumerate() {
+            let status = node.status().await;
+            if status.role == NodeRole::Leader {
+                leaders.push((i, status.current_term));
+            }
+        }
+        leaders
+    }
+
+    /// Waits for a connected node to lead, returning its index, None if none does within timeout.
+    pub async fn wait_for_leader(&self, timeout: Duration) -> Option<usize> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let connected = self.connected();
+            let leader = self
+                .leaders()
+                .await
+                .into_iter()
+                .filter(|(i, _)| connected.contains(i))
+                .max_by_key(|(_, term)| *term);
+            if let Some((i, _)) = leader {
+                return Some(i);
+            } else if Instant::now() >= deadline {
+                return None;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// Panics unless exactly one node leads in term, returns its index.
+    pub async fn assert_one_leader_in_term(&self, term: u64) -> usize {
+        let leaders: Vec<usize> = self
+            .leaders()
+            .await
+            .into_iter()
+            .filter(|(_, leader_term)| *leader_term == term)
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(
+            leaders.len(),
+            1,
+            "expected one leader in term {}, found {:?}",
+            term,
+            leaders
+        );
+        leaders[0]
+    }
+
+    /// Waits for all connected nodes to apply every entry committed by any of them, then panics
+    /// unless their state machines have the same snapshot. Also panics if they don't catch up
+    /// within timeout.
+    pub async fn assert_state_machines_agree(&self, timeout: Duration) {
+        let connected = self.connected();
+        let mut committed = 0;
+        for &i in &connected {
+            committed = committed.max(self.nodes[i].status().await.commit_index);
+        }
+
+        let deadline = Instant::now() + timeout;
+        for &i in &connected {
+            while self.nodes[i].status().await.last_applied < committed {
+                assert!(
+                    Instant::now() < deadline,
+                    "node {} didn't apply upto {} in time",
+                    i,
+                    committed
+                );
+                sleep(Duration::from_millis(10)).await;
+            }
+        }
+
+        let mut snapshots = vec![];
+        for &i in &connected {
+            let state_machine = self.nodes[i].state_machine();
+            let snapshot = state_machine.lock().await.snapshot();
+            snapshots.push((i, snapshot));
+        }
+        for (i, snapshot) in &snapshots {
+            assert!(
+                *snapshot == snapshots[0].1,
+                "state machine of node {} differs from that of node {}",
+                i,
+                snapshots[0].0
+            );
+        }
+    }
+
+    /// Shuts every node down.
+    pub async fn shutdown(&self) -> Result<(), RaftError> {
+        for node in &self.nodes {
+            node.shutdown().await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,447 @@
+use rand::Rng;
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+#[cfg(feature = "tls")]
+use tonic::transport::ClientTlsConfig;
+use tonic::{transport::Channel, Code, Request, Response, Status};
+use tracing::debug;
+
+use crate::{
+    config,
+    error::RaftError,
+    raft_proto::{
+        raft_client::RaftClient, raft_server::Raft, Byte, EntryReply, EntryRequest, Null,
+        ProposeReply, SnapshotReply, SnapshotRequest, TimeoutReply, TimeoutRequest, VoteReply,
+        VoteRequest,
+    },
+};
+
+/// Carries RPCs from a node to the node at some address, mirroring the RPCs it serves. Requests
+/// that can't be delivered fail with `Status::unavailable`.
+#[tonic::async_trait]
+pub trait RaftTransport: Send + Sync {
+    async fn request_vote(
+        &self,
+        node: &str,
+        request: Request<VoteRequest>,
+    ) -> Result<Response<VoteReply>, Status>;
+    async fn append_entries(
+        &self,
+        node: &str,
+        request: Request<EntryRequest>,
+    ) -> Result<Response<EntryReply>, Status>;
+    async fn install_snapshot(
+        &self,
+        node: &str,
+        request: Request<SnapshotRequest>,
+    ) -> Result<Response<SnapshotReply>, Status>;
+    async fn timeout_now(
+        &self,
+        node: &str,
+        request: Request<TimeoutRequest>,
+    ) -> Result<Response<TimeoutReply>, Status>;
+    async fn join(&self, node: &str, request: Request<Byte>) -> Result<Response<Null>, Status>;
+    async fn leave(&self, node: &str, request: Request<Byte>) -> Result<Response<Null>, Status>;
+    async fn propose(
+        &self,
+        node: &str,
+        request: Request<Byte>,
+    ) -> Result<Response<ProposeReply>, Status>;
+
+    /// Lets go of whatever is held to reach the node at addr, once it's no longer a member.
+    fn forget(&self, _node: &str) {}
+
+    /// Checks that node is an address the transport can reach nodes at, before it is made a
+    /// member or dialed. Any address is accepted unless the transport overrides this.
+    fn validate_addr(&self, _node: &str) -> Result<(), RaftError> {
+        Ok(())
+    }
+}
+
+/// Sends RPCs over gRPC, keeping one connection to each node. Nodes are dialed on the first RPC
+/// to them, and again on the next one after a connection fails. Clones share the connections.
+/// Hostnames are resolved each time a node is dialed, so nodes that moved to another IP address
+/// are reached once their old connection failed.
+#[derive(Debug, Default, Clone)]
+pub struct GrpcTransport {
+    /// Connection to each node dialed so far
+    clients: Arc<Mutex<HashMap<String, RaftClient<Channel>>>>,
+    /// Longest time to wait for a connection to a node, dialing may take as long as it does if None
+    connect_timeout: Option<Duration>,
+    /// Settings with which nodes are dialed over TLS, in plaintext if None
+    #[cfg(feature = "tls")]
+    tls: Option<ClientTlsConfig>,
+}
+
+impl GrpcTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Transport dialing nodes over TLS with settings such as those of `TlsConfig::client_config`.
+    #[cfg(feature = "tls")]
+    pub fn with_tls(tls: ClientTlsConfig) -> Self {
+        Self {
+            tls: Some(tls),
+            ..Self::default()
+        }
+    }
+
+    /// Fails to dial nodes with `Status::unavailable` unless connected within timeout, such as
+    /// `Config::connect_timeout`, which nodes started with `RaftNode::start` apply.
+    pub fn with_connect_timeout(self, timeout: Duration) -> Self {
+        Self {
+            connect_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Whether a connection to node is kept, which the next RPC to it goes over.
+    pub fn is_connected(&self, node: &str) -> bool {
+        self.clients.lock().unwrap().contains_key(node)
+    }
+
+    /// Connection to node, dialing it unless already connected.
+    async fn client(&self, node: &str) -> Result<RaftClient<Channel>, Status> {
+        if let Some(client) = self.clients.lock().unwrap().get(node) {
+            return Ok(client.clone());
+        }
+        let client = self.connect(node).await?;
+        let mut clients = self.clients.lock().unwrap();
+        // Another RPC may have connected meanwhile, whichever connection came first is kept
+        let client = clients.entry(node.to_string()).or_insert(client).clone();
+        debug!(node, connections = clients.len(), "connected to node");
+        Ok(client)
+    }
+
+    async fn connect(&self, node: &str) -> Result<RaftClient<Channel>, Status> {
+        let timeout = match self.connect_timeout {
+            Some(timeout) => timeout,
+            None => return self.dial(node).await,
+        };
+        match tokio::time::timeout(timeout, self.dial(node)).await {
+            Ok(client) => client,
+            Err(_) => Err(Status::unavailable(format!(
+                "Not connected within {:?}",
+                timeout
+            ))),
+        }
+    }
+
+    async fn dial(&self, node: &str) -> Result<RaftClient<Channel>, Status> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            let channel = Channel::from_shared(format!("https://{}", node))
+                .map_err(|e| Status::unavailable(e.to_string()))?
+                .tls_config(tls.clone())
+                .map_err(|e| Status::unavailable(e.to_string()))?
+                .connect()
+                .await
+                .map_err(|e| Status::unavailable(e.to_string()))?;
+            return Ok(RaftClient::new(channel));
+        }
+        RaftClient::connect(format!("http://{}", node))
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))
+    }
+
+    /// Drops the connection to node if the RPC failed for want of it, so that the next RPC dials
+    /// the node again.
+    fn check<T>(&self, node: &str, result: &Result<T, Status>) {
+        if let Err(status) = result {
+            if matches!(status.code(), Code::Unavailable | Code::Unknown) {
+                self.forget(node);
+            }
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl RaftTransport for GrpcTransport {
+    async fn request_vote(
+        &self,
+        node: &str,
+        request: Request<VoteRequest>,
+    ) -> Result<Response<VoteReply>, Status> {
+        let reply = self.client(node).await?.request_vote(request).await;
+        self.check(node, &reply);
+        reply
+    }
+
+    async fn append_entries(
+        &self,
+        node: &str,
+        request: Request<EntryRequest>,
+    ) -> Result<Response<EntryReply>, Status> {
+        let reply = self.client(node).await?.append_entries(request).await;
+        self.check(node, &reply);
+        reply
+    }
+
+    async fn install_snapshot(
+        &self,
+        node: &str,
+        request: Request<SnapshotRequest>,
+    ) -> Result<Response<SnapshotReply>, Status> {
+        let reply = self.client(node).await?.install_snapshot(request).await;
+        self.check(node, &reply);
+        reply
+    }
+
+    async fn timeout_now(
+        &self,
+        node: &str,
+        request: Request<TimeoutRequest>,
+    ) -> Result<Response<TimeoutReply>, Status> {
+        let reply = self.client(node).await?.timeout_now(request).await;
+        self.check(node, &reply);
+        reply
+    }
+
+    async fn join(&self, node: &str, request: Request<Byte>) -> Result<Response<Null>, Status> {
+        let reply = self.client(node).await?.join(request).await;
+        self.check(node, &reply);
+        reply
+    }
+
+    async fn leave(&self, node: &str, request: Request<Byte>) -> Result<Response<Null>, Status> {
+        let reply = self.client(node).await?.leave(request).await;
+        self.check(node, &reply);
+        reply
+    }
+
+    async fn propose(
+        &self,
+        node: &str,
+        request: Request<Byte>,
+    ) -> Result<Response<ProposeReply>, Status> {
+        let reply = self.client(node).await?.propose(request).await;
+        self.check(node, &reply);
+        reply
+    }
+
+    fn forget(&self, node: &str) {
+        self.clients.lock().unwrap().remove(node);
+    }
+
+    fn validate_addr(&self, node: &str) -> Result<(), RaftError> {
+        config::validate_addr(node)
+    }
+}
+
+/// Delivers RPCs to nodes within the same process by calling them directly, without any sockets,
+/// so that a whole cluster can run inside a test. Clones share the nodes registered with them.
+///
+/// Nodes started with this transport don't serve gRPC, each must be registered under its
+/// address once started. Each node should be given a transport bound to its own address, so
+/// that disconnecting it cuts it off from the others in both directions, as in a partition.
+///
+/// Messages may also be dropped and delayed at random, with delays timed by tokio's clock.
+#[derive(Default, Clone)]
+pub struct LocalTransport {
+    network: Arc<RwLock<Network>>,
+    /// Address of the node sending through this transport, if bound to one
+    local: Option<String>,
+}
+
+#[derive(Default)]
+struct Network {
+    nodes: HashMap<String, Arc<dyn Raft>>,
+    disconnected: HashSet<String>,
+    /// Links from one node to another on which RPCs are dropped
+    cut: HashSet<(String, String)>,
+    /// Chance of each request and each reply being dropped
+    drop_rate: f64,
+    /// Shortest and longest time requests take to arrive, drawn at random for each
+    delay: (Duration, Duration),
+}
+
+impl LocalTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Transport for the node at addr, sharing this one's nodes.
+    pub fn bind(&self, addr: &str) -> Self {
+        Self {
+            network: self.network.clone(),
+            local: Some(addr.to_string()),
+        }
+    }
+
+    /// Delivers RPCs addressed to addr to node from now on.
+    pub fn register(&self, addr: String, node: impl Raft) {
+        self.network
+            .write()
+            .unwrap()
+            .nodes
+            .insert(addr, Arc::new(node));
+    }
+
+    /// Fails RPCs to and from the node at addr as if it was unreachable, until it is reconnected.
+    pub fn disconnect(&self, addr: &str) {
+        self.network
+            .write()
+            .unwrap()
+            .disconnected
+            .insert(addr.to_string());
+    }
+
+    /// Delivers RPCs to and from the node at addr again.
+    pub fn reconnect(&self, addr: &str) {
+        self.network.write().unwrap().disconnected.remove(addr);
+    }
+
+    /// Drops RPCs sent by the node at from to the node at to, but not the other way around, until
+    /// the link is restored.
+    pub fn cut_link(&self, from: &str, to: &str) {
+        self.network
+            .write()
+            .unwrap()
+            .cut
+            .insert((from.to_string(), to.to_string()));
+    }
+
+    /// Delivers RPCs sent by the node at from to the node at to again.
+    pub fn restore_link(&self, from: &str, to: &str) {
+        self.network
+            .write()
+            .unwrap()
+            .cut
+            .remove(&(from.to_string(), to.to_string()));
+    }
+
+    /// Drops each request and each reply with probability rate from now on, as a lossy network
+    /// does, so that a node may handle a request whose sender never hears back. Zero delivers
+    /// them all again.
+    pub fn set_drop_rate(&self, rate: f64) {
+        self.network.write().unwrap().drop_rate = rate.clamp(0.0, 1.0);
+    }
+
+    /// Holds back each request for a time drawn at random between min and max before delivering
+    /// it, so that requests sent one after another may arrive out of order. Zero for both delivers
+    /// them right away again.
+    pub fn set_delay(&self, min: Duration, max: Duration) {
+        self.network.write().unwrap().delay = (min, max.max(min));
+    }
+
+    fn node(&self, addr: &str) -> Option<Arc<dyn Raft>> {
+        let network = self.network.read().unwrap();
+        let cut_off = |addr: &str| network.disconnected.contains(addr);
+        if cut_off(addr) || self.local.as_deref().is_some_and(cut_off) {
+            return None;
+        }
+        if let Some(local) = &self.local {
+            if network.cut.contains(&(local.clone(), addr.to_string())) {
+                return None;
+            }
+        }
+        network.nodes.get(addr).cloned()
+    }
+
+    /// Delivers rpc to the node at addr, after its delay unless dropped. Links are checked once
+    /// the request arrives, so that partitions also cut off requests that were in flight.
+    async fn deliver<T, F>(
+        &self,
+        addr: &str,
+        rpc: impl FnOnce(Arc<dyn Raft>) -> F,
+    ) -> Result<Response<T>, Status>
+    where
+        F: Future<Output = Result<Response<T>, Status>>,
+    {
+        let (drop_rate, (min, max)) = {
+            let network = self.network.read().unwrap();
+            (network.drop_rate, network.delay)
+        };
+        if max > Duration::from_secs(0) {
+            let delay = rand::thread_rng().gen_range(min..=max);
+            tokio::time::sleep(delay).await;
+        }
+        let dropped = || rand::thread_rng().gen_bool(drop_rate);
+        let node = self.node(addr).ok_or_else(|| unreachable(addr))?;
+        if dropped() {
+            return Err(lost(addr));
+        }
+        let reply = rpc(node).await?;
+        match dropped() {
+            true => Err(lost(addr)),
+            false => Ok(reply),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl RaftTransport for LocalTransport {
+    async fn request_vote(
+        &self,
+        node: &str,
+        request: Request<VoteRequest>,
+    ) -> Result<Response<VoteReply>, Status> {
+        self.deliver(node, |node| async move { node.request_vote(request).await })
+            .await
+    }
+
+    async fn append_entries(
+        &self,
+        node: &str,
+        request: Request<EntryRequest>,
+    ) -> Result<Response<EntryReply>, Status> {
+        self.deliver(
+            node,
+            |node| async move { node.append_entries(request).await },
+        )
+        .await
+    }
+
+    async fn install_snapshot(
+        &self,
+        node: &str,
+        request: Request<SnapshotRequest>,
+    ) -> Result<Response<SnapshotReply>, Status> {
+        self.deliver(
+            node,
+            |node| async move { node.install_snapshot(request).await },
+        )
+        .await
+    }
+
+    async fn timeout_now(
+        &self,
+        node: &str,
+        request: Request<TimeoutRequest>,
+    ) -> Result<Response<TimeoutReply>, Status> {
+        self.deliver(node, |node| async move { node.timeout_now(request).await })
+            .await
+    }
+
+    async fn join(&self, node: &str, request: Request<Byte>) -> Result<Response<Null>, Status> {
+        self.deliver(node, |node| async move { node.join(request).await })
+            .await
+    }
+
+    async fn leave(&self, node: &str, request: Request<Byte>) -> Result<Response<Null>, Status> {
+        self.deliver(node, |node| async move { node.leave(request).await })
+            .await
+    }
+
+    async fn propose(
+        &self,
+        node: &str,
+        request: Request<Byte>,
+    ) -> Result<Response<ProposeReply>, Status> {
+        self.deliver(node, |node| async move { node.propose(request).await })
+            .await
+    }
+}
+
+fn unreachable(addr: &str) -> Status {
+    Status::unavailable(format!("No node at {}", addr))
+}
+
+fn lost(addr: &str) -> Status {
+    Status::unavailable(format!("Message to or from {} was dropped", addr))
+}
@@ -0,0 +1,28 @@
+use raft::{
+    node::RaftNode,
+    raft::NodeRole,
+    state_machine::{RaftCommand, RaftTask},
+};
+use tokio::time::{self, Duration};
+
+#[tokio::test(start_paused = true)]
+async fn bootstrapped_node_commits_on_its_own() {
+    let node = RaftNode::bootstrap(0, "127.0.0.1:0".to_string())
+        .await
+        .unwrap();
+    let status = node.status().await;
+    assert_eq!(status.role, NodeRole::Leader);
+    assert_eq!(status.commit_index, 1);
+
+    // Node isn't even running, no election timeout has to pass for it to commit
+    let command = RaftCommand {
+        task: RaftTask::Occupy,
+        node: 0,
+        data: vec![],
+    };
+    let propose = node.propose(command);
+    let index = time::timeout(Duration::from_millis(1), propose).await;
+    let index = index.unwrap().unwrap();
+    assert_eq!(node.status().await.commit_index, index);
+    node.shutdown().await.unwrap();
+}
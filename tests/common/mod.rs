@@ -0,0 +1,166 @@
+//! Helpers shared by the integration tests, those running a `TestCluster` need the `testing`
+//! feature to build.
+#![allow(dead_code)]
+
+use raft::{
+    config::Config,
+    error::DecodeError,
+    node::RaftNode,
+    raft_proto::{Entry, EntryReply, EntryRequest, VoteReply, VoteRequest},
+    state_machine::StateMachine,
+    transport::LocalTransport,
+};
+use std::{convert::TryInto, path::PathBuf};
+
+/// Addresses of a cluster of size nodes, as `TestCluster` names them.
+pub fn addrs(size: usize) -> Vec<String> {
+    (0..size).map(|i| format!("node-{}", i)).collect()
+}
+
+/// Node at index i of a cluster of size nodes, registered with transport but not running, so
+/// that a test alone decides which RPCs it handles.
+pub async fn idle_node(transport: &LocalTransport, i: usize, size: usize) -> RaftNode {
+    let addrs = addrs(size);
+    let node = RaftNode::builder(i as u64, addrs[i].clone(), addrs.clone())
+        .transport(transport.bind(&addrs[i]))
+        .start()
+        .await
+        .unwrap();
+    transport.register(addrs[i].clone(), node.clone());
+    node
+}
+
+/// Runs a node at addr alongside those of a cluster on transport, started with the addresses of
+/// nodes, like a node added to the cluster would be.
+pub async fn spawn_node<S: StateMachine + Default>(
+    transport: &LocalTransport,
+    id: u64,
+    addr: &str,
+    nodes: Vec<String>,
+    config: Config,
+) -> RaftNode<S> {
+    let node = RaftNode::builder(id, addr.to_string(), nodes)
+        .state_machine(S::default())
+        .transport(transport.bind(addr))
+        .start()
+        .await
+        .unwrap();
+    transport.register(addr.to_string(), node.clone());
+    let mut runner = node.clone();
+    tokio::spawn(async move {
+        let _ = runner.run(config).await;
+    });
+    node
+}
+
+/// Config of the nodes of a test, as a cluster would run with by default.
+pub fn config() -> Config {
+    Config::default()
+}
+
+/// State machine recording every command applied to it along with the index of its entry.
+#[derive(Default)]
+pub struct Recorder {
+    pub applied: Vec<(u64, Vec<u8>)>,
+}
+
+impl StateMachine for Recorder {
+    type Command = Vec<u8>;
+
+    fn apply(&mut self, index: u64, command: Vec<u8>) -> Vec<u8> {
+        self.applied.push((index, command.clone()));
+        command
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        for (index, command) in &self.applied {
+            bytes.extend_from_slice(&index.to_be_bytes());
+            bytes.extend_from_slice(&(command.len() as u64).to_be_bytes());
+            bytes.extend_from_slice(command);
+        }
+        bytes
+    }
+
+    fn restore(&mut self, mut snapshot: &[u8]) -> Result<(), DecodeError> {
+        let truncated = || DecodeError("truncated snapshot".to_string());
+        let mut applied = vec![];
+        while !snapshot.is_empty() {
+            let header = snapshot.get(..16).ok_or_else(truncated)?;
+            let index = u64::from_be_bytes(header[..8].try_into().unwrap());
+            let len = u64::from_be_bytes(header[8..].try_into().unwrap()) as usize;
+            let command = snapshot[16..].get(..len).ok_or_else(truncated)?;
+            applied.push((index, command.to_vec()));
+            snapshot = &snapshot[16 + len..];
+        }
+        self.applied = applied;
+        Ok(())
+    }
+}
+
+/// Commands applied by the state machine of node, in the order it applied them.
+pub async fn applied(node: &RaftNode<Recorder>) -> Vec<(u64, Vec<u8>)> {
+    node.state_machine().lock().await.applied.clone()
+}
+
+/// Normal entry at index of term, carrying index as its data.
+pub fn entry(index: u64, term: u64) -> Entry {
+    Entry {
+        index,
+        term,
+        data: index.to_be_bytes().to_vec(),
+        kind: 0,
+    }
+}
+
+/// Asks node for its vote in term, as the candidate at index i with a log ending at
+/// `last_log_index` of `last_log_term`.
+pub async fn request_vote<S: StateMachine>(
+    node: &RaftNode<S>,
+    term: u64,
+    i: usize,
+    last_log_index: u64,
+    last_log_term: u64,
+) -> VoteReply {
+    let request = VoteRequest {
+        term,
+        candidate_id: i as u64,
+        last_log_index,
+        last_log_term,
+        candidate_addr: format!("node-{}", i),
+        pre_vote: false,
+    };
+    let reply = node.step(request.into()).await.unwrap();
+    reply.try_into().unwrap()
+}
+
+/// Sends node entries following `prev_log_index` of `prev_log_term`, as the leader of term at
+/// index i with commit index at commit.
+pub async fn append_entries<S: StateMachine>(
+    node: &RaftNode<S>,
+    term: u64,
+    i: usize,
+    (prev_log_index, prev_log_term): (u64, u64),
+    entries: Vec<Entry>,
+    commit_index: u64,
+) -> EntryReply {
+    let request = EntryRequest {
+        term,
+        id: i as u64,
+        prev_log_index,
+        prev_log_term,
+        entries,
+        commit_index,
+        leader_addr: format!("node-{}", i),
+    };
+    let reply = node.step(request.into()).await.unwrap();
+    reply.try_into().unwrap()
+}
+
+/// Empty directory of its own for the test named name.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("raft-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
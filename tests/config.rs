@@ -0,0 +1,125 @@
+#![cfg(feature = "toml")]
+
+mod common;
+
+use raft::{config::Config, error::RaftError, node::RaftNode};
+use std::{env, fs, net::TcpListener, sync::Mutex};
+
+use common::temp_dir;
+
+/// Held by tests setting environment variables, which all threads of the process share.
+static ENV: Mutex<()> = Mutex::new(());
+
+/// Config loaded from the environment with vars set on top of the address of a single node.
+fn from_env(vars: &[(&str, &str)]) -> Result<Config, RaftError> {
+    let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+    let vars = [("RAFT_ADDR", "127.0.0.1:50051"), ("RAFT_NODES", "127.0.0.1:50051")]
+        .iter()
+        .chain(vars);
+    for (var, value) in vars.clone() {
+        env::set_var(var, value);
+    }
+    let config = Config::from_env();
+    for (var, _) in vars {
+        env::remove_var(var);
+    }
+    config
+}
+
+#[test]
+fn env_values_take_the_type_of_their_field() {
+    let config = from_env(&[
+        ("RAFT_ELECTION_TIMEOUT_MIN", "200"),
+        ("RAFT_PRE_VOTE", "false"),
+        ("RAFT_LEASE_FACTOR", "1"),
+    ]);
+    let config = config.unwrap();
+    assert_eq!(config.election_timeout_min, 200);
+    assert!(!config.pre_vote);
+    assert_eq!(config.lease_factor, 1.0);
+
+    match from_env(&[("RAFT_PRE_VOTE", "1")]) {
+        Err(RaftError::InvalidConfig(reason)) => assert!(reason.contains("RAFT_PRE_VOTE")),
+        result => panic!("expected InvalidConfig, got {:?}", result),
+    }
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn env_tls_values_take_the_type_of_their_field() {
+    let config = from_env(&[
+        ("RAFT_TLS_CERT", "node.pem"),
+        ("RAFT_TLS_KEY", "node.key"),
+        ("RAFT_TLS_CA", "ca.pem"),
+        ("RAFT_TLS_DOMAIN", "raft.local"),
+        ("RAFT_TLS_VERIFY_PEERS", "true"),
+    ]);
+    let tls = config.unwrap().tls.unwrap();
+    assert!(tls.verify_peers);
+    assert_eq!(tls.domain, "raft.local");
+
+    // Strings stay strings however they look
+    let config = from_env(&[
+        ("RAFT_TLS_CERT", "true"),
+        ("RAFT_TLS_KEY", "inf"),
+        ("RAFT_TLS_CA", "ca.pem"),
+        ("RAFT_TLS_DOMAIN", "123"),
+    ]);
+    let tls = config.unwrap().tls.unwrap();
+    assert_eq!(tls.cert.to_str(), Some("true"));
+    assert_eq!(tls.key.to_str(), Some("inf"));
+    assert_eq!(tls.domain, "123");
+}
+
+#[test]
+fn unknown_env_variable_is_named() {
+    match from_env(&[("RAFT_UNRELATED", "1")]) {
+        Err(RaftError::InvalidConfig(reason)) => assert!(reason.contains("RAFT_UNRELATED")),
+        result => panic!("expected InvalidConfig, got {:?}", result),
+    }
+}
+
+#[tokio::test]
+async fn config_file_starts_a_node() {
+    let path = temp_dir("config-file").join("raft.toml");
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .unwrap();
+    let toml = format!(
+        r#"
+        id = 2
+        addr = "{addr}"
+        nodes = ["{addr}", "127.0.0.1:1"]
+        election_timeout_min = 200
+        election_timeout_max = 400
+        heartbeat_interval = 40
+        "#,
+        addr = addr
+    );
+    fs::write(&path, toml).unwrap();
+
+    let config = Config::from_file(&path).unwrap();
+    assert_eq!((config.id, config.addr.clone()), (2, addr.to_string()));
+    assert_eq!(config.nodes, [addr.to_string(), "127.0.0.1:1".to_string()]);
+    assert_eq!(config.election_timeout_min, 200);
+    assert_eq!(config.election_timeout_max, 400);
+    assert_eq!(config.heartbeat_interval, 40);
+    let node = RaftNode::start_from(&config).await.unwrap();
+    assert_eq!(node.status().await.id, 2);
+    node.shutdown().await.unwrap();
+}
+
+#[test]
+fn config_file_must_list_the_node() {
+    let path = temp_dir("config-file-missing").join("raft.toml");
+    let toml = r#"
+        addr = "127.0.0.1:50051"
+        nodes = ["127.0.0.1:50052", "127.0.0.1:50053"]
+    "#;
+    fs::write(&path, toml).unwrap();
+
+    match Config::from_file(&path) {
+        Err(RaftError::InvalidConfig(reason)) => assert!(reason.contains("127.0.0.1:50051")),
+        result => panic!("expected InvalidConfig, got {:?}", result),
+    }
+}
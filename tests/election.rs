@@ -0,0 +1,407 @@
+#![cfg(feature = "testing")]
+
+mod common;
+
+use raft::{
+    clock::ManualClock,
+    config::Config,
+    error::RaftError,
+    node::{Message, RaftNode},
+    raft::NodeRole,
+    raft_proto::{
+        raft_server::Raft, Byte, EntryReply, EntryRequest, Null, ProposeReply, SnapshotReply,
+        SnapshotRequest, TimeoutReply, TimeoutRequest, VoteReply, VoteRequest,
+    },
+    state_machine::{RaftCommand, RaftTask},
+    testing::TestCluster,
+    transport::LocalTransport,
+};
+use std::future;
+use tokio::time::{self, Duration, Instant};
+use tonic::{Request, Response, Status};
+
+use common::{
+    addrs, append_entries, config, entry, idle_node, request_vote, spawn_node, temp_dir,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Command for the default state machine of the nodes.
+fn occupy() -> RaftCommand {
+    RaftCommand {
+        task: RaftTask::Occupy,
+        node: 0,
+        data: vec![],
+    }
+}
+
+/// Node that accepts every RPC but never replies to any.
+struct Unresponsive;
+
+#[tonic::async_trait]
+impl Raft for Unresponsive {
+    async fn request_vote(&self, _: Request<VoteRequest>) -> Result<Response<VoteReply>, Status> {
+        future::pending().await
+    }
+
+    async fn append_entries(
+        &self,
+        _: Request<EntryRequest>,
+    ) -> Result<Response<EntryReply>, Status> {
+        future::pending().await
+    }
+
+    async fn install_snapshot(
+        &self,
+        _: Request<SnapshotRequest>,
+    ) -> Result<Response<SnapshotReply>, Status> {
+        future::pending().await
+    }
+
+    async fn timeout_now(
+        &self,
+        _: Request<TimeoutRequest>,
+    ) -> Result<Response<TimeoutReply>, Status> {
+        future::pending().await
+    }
+
+    async fn join(&self, _: Request<Byte>) -> Result<Response<Null>, Status> {
+        future::pending().await
+    }
+
+    async fn leave(&self, _: Request<Byte>) -> Result<Response<Null>, Status> {
+        future::pending().await
+    }
+
+    async fn propose(&self, _: Request<Byte>) -> Result<Response<ProposeReply>, Status> {
+        future::pending().await
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn votes_once_per_term() {
+    let transport = LocalTransport::new();
+    let node = idle_node(&transport, 0, 3).await;
+
+    let first = request_vote(&node, 1, 1, 0, 0).await;
+    let second = request_vote(&node, 1, 2, 0, 0).await;
+    assert!(first.grant);
+    assert!(!second.grant);
+    // Candidate it voted for may ask again, say after its request was lost
+    assert!(request_vote(&node, 1, 1, 0, 0).await.grant);
+    assert_eq!(node.status().await.current_term, 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn candidate_with_shorter_log_is_refused() {
+    let transport = LocalTransport::new();
+    let node = idle_node(&transport, 0, 3).await;
+    let entries = vec![entry(1, 1), entry(2, 1), entry(3, 1)];
+    assert!(append_entries(&node, 1, 1, (0, 0), entries, 0).await.success);
+
+    assert!(!request_vote(&node, 2, 2, 2, 1).await.grant);
+    // Log of an older last term is behind however long it is
+    assert!(!request_vote(&node, 2, 2, 5, 0).await.grant);
+    assert!(request_vote(&node, 2, 2, 3, 1).await.grant);
+}
+
+#[tokio::test(start_paused = true)]
+async fn restarted_node_keeps_its_vote() {
+    let dir = temp_dir("restarted-vote");
+    let start = || async {
+        let transport = LocalTransport::new();
+        RaftNode::builder(0, addrs(3)[0].clone(), addrs(3))
+            .transport(transport.bind("node-0"))
+            .path(dir.clone())
+            .start()
+            .await
+            .unwrap()
+    };
+
+    // Node crashes right after granting its vote
+    let node = start().await;
+    assert!(request_vote(&node, 2, 1, 0, 0).await.grant);
+    drop(node);
+
+    let node = start().await;
+    assert_eq!(node.status().await.current_term, 2);
+    assert!(!request_vote(&node, 2, 2, 0, 0).await.grant);
+    assert!(request_vote(&node, 2, 1, 0, 0).await.grant);
+}
+
+#[tokio::test(start_paused = true)]
+async fn followers_hearing_heartbeats_never_campaign() {
+    let cluster = TestCluster::new(3, config()).await.unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    let mut elections = vec![];
+    for i in 0..cluster.len() {
+        elections.push(cluster.node(i).metrics().await.elections_started);
+    }
+
+    cluster.advance(Duration::from_secs(30)).await;
+    for (i, started) in elections.into_iter().enumerate() {
+        let metrics = cluster.node(i).metrics().await;
+        assert_eq!(metrics.elections_started, started, "node {} campaigned", i);
+        assert_eq!(metrics.leader_id, Some(leader as u64));
+    }
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn leadership_stays_stable_for_minutes() {
+    let cluster = TestCluster::new(3, config()).await.unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    let term = cluster.node(leader).status().await.current_term;
+    let heartbeats = cluster.node(leader).metrics().await.heartbeats_sent;
+
+    cluster.advance(Duration::from_secs(5 * 60)).await;
+    assert_eq!(cluster.assert_one_leader_in_term(term).await, leader);
+    assert!(cluster.node(leader).metrics().await.heartbeats_sent > heartbeats);
+    for i in 0..cluster.len() {
+        assert_eq!(cluster.node(i).status().await.current_term, term);
+    }
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn leader_hearing_newer_heartbeat_steps_down() {
+    let cluster = TestCluster::new(3, config()).await.unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    let term = cluster.node(leader).status().await.current_term;
+    let other = (leader + 1) % cluster.len();
+
+    let node = cluster.node(leader);
+    let reply = append_entries(node, term + 1, other, (0, 0), vec![], 0).await;
+    assert!(reply.success);
+    let status = node.status().await;
+    assert_eq!(status.role, NodeRole::Follower);
+    assert_eq!(status.current_term, term + 1);
+    assert_eq!(status.leader.as_deref(), Some(cluster.addr(other)));
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn heartbeats_keep_follower_from_timing_out() {
+    let clock = ManualClock::new();
+    let node = RaftNode::builder(1, "node-1".to_string(), addrs(3))
+        .transport(LocalTransport::new().bind("node-1"))
+        .clock(clock.clone())
+        .start()
+        .await
+        .unwrap();
+    let config = Config {
+        pre_vote: false,
+        ..config()
+    };
+    node.configure(&config).await.unwrap();
+
+    for _ in 0..200 {
+        assert!(append_entries(&node, 1, 0, (0, 0), vec![], 0).await.success);
+        clock.advance(config.heartbeat_interval());
+        node.tick().await.unwrap();
+    }
+    assert_eq!(node.status().await.role, NodeRole::Follower);
+    assert_eq!(node.metrics().await.elections_started, 0);
+
+    // Once heartbeats stop, it campaigns within an election timeout
+    clock.advance(config.election_timeout_max());
+    node.tick().await.unwrap();
+    assert_eq!(node.metrics().await.elections_started, 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn rejoining_node_doesnt_depose_leader() {
+    let cluster = TestCluster::new(3, config()).await.unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    let term = cluster.node(leader).status().await.current_term;
+    let follower = (leader + 1) % cluster.len();
+
+    cluster.partition(follower);
+    cluster.advance(Duration::from_secs(5)).await;
+    cluster.heal(follower);
+    cluster.advance(Duration::from_secs(5)).await;
+    assert_eq!(cluster.assert_one_leader_in_term(term).await, leader);
+    let status = cluster.node(follower).status().await;
+    assert_eq!(status.leader.as_deref(), Some(cluster.addr(leader)));
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn isolated_leader_steps_down() {
+    let config = config();
+    let cluster = TestCluster::new(3, config.clone()).await.unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+
+    cluster.partition(leader);
+    // Quorum is checked every shortest election timeout, over the timeout before
+    cluster.advance(config.election_timeout_min() * 2).await;
+    assert_ne!(cluster.node(leader).status().await.role, NodeRole::Leader);
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn subscribers_see_the_node_win_an_election() {
+    let transport = LocalTransport::new();
+    for i in 1..3 {
+        idle_node(&transport, i, 3).await;
+    }
+    let node: RaftNode = spawn_node(&transport, 0, "node-0", addrs(3), config()).await;
+    let mut status = node.subscribe().await;
+    assert_eq!(status.borrow().role, NodeRole::Follower);
+    assert_eq!(status.borrow().current_term, 0);
+
+    // Idle peers never campaign, they only grant the vote node-0 asks for. Votes take a while to
+    // arrive, so that the receiver wakes up while node-0 is still a candidate
+    transport.set_delay(Duration::from_millis(10), Duration::from_millis(10));
+    let mut roles = vec![(NodeRole::Follower, 0)];
+    while roles.last().unwrap().0 != NodeRole::Leader {
+        time::timeout(TIMEOUT, status.changed()).await.unwrap().unwrap();
+        let status = status.borrow_and_update();
+        if status.role != roles.last().unwrap().0 {
+            roles.push((status.role, status.current_term));
+        }
+    }
+    assert_eq!(
+        roles,
+        [
+            (NodeRole::Follower, 0),
+            (NodeRole::Candidate, 1),
+            (NodeRole::Leader, 1)
+        ]
+    );
+    node.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn isolated_node_doesnt_bump_the_term() {
+    let config = config();
+    let cluster = TestCluster::new(3, config.clone()).await.unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    let term = cluster.node(leader).status().await.current_term;
+    let follower = (leader + 1) % cluster.len();
+    let elections = cluster.node(follower).metrics().await.elections_started;
+
+    cluster.partition(follower);
+    cluster.advance(config.election_timeout_max() * 10).await;
+    // Pre-votes it asked for went unanswered, so it never started an election of its own
+    let metrics = cluster.node(follower).metrics().await;
+    assert_eq!(metrics.current_term, term);
+    assert_eq!(metrics.elections_started, elections);
+
+    cluster.heal(follower);
+    cluster.advance(config.election_timeout_max()).await;
+    for i in 0..cluster.len() {
+        assert_eq!(cluster.node(i).status().await.current_term, term, "node {}", i);
+    }
+    assert_eq!(cluster.assert_one_leader_in_term(term).await, leader);
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn leadership_moves_to_the_named_node() {
+    let config = config();
+    let cluster = TestCluster::new(3, config.clone()).await.unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    let term = cluster.node(leader).status().await.current_term;
+    let target = (leader + 1) % cluster.len();
+
+    let started = Instant::now();
+    let node = cluster.node(leader);
+    node.transfer_leadership(cluster.addr(target).to_string()).await.unwrap();
+    assert!(started.elapsed() <= config.election_timeout_max());
+    assert_eq!(cluster.assert_one_leader_in_term(term + 1).await, target);
+    assert_eq!(node.status().await.role, NodeRole::Follower);
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn leader_reaching_a_majority_keeps_leading() {
+    let config = config();
+    let cluster = TestCluster::new(3, config.clone()).await.unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    let term = cluster.node(leader).status().await.current_term;
+
+    // Quorum checks keep passing on the replies of the one follower still reached
+    let follower = (leader + 1) % cluster.len();
+    cluster.drop_messages(leader, follower);
+    cluster.drop_messages(follower, leader);
+    cluster.advance(config.election_timeout_min() * 10).await;
+    assert_eq!(cluster.node(leader).status().await.role, NodeRole::Leader);
+    assert_eq!(cluster.node(leader).status().await.current_term, term);
+
+    // Once the other is cut off as well, it steps down within two quorum checks
+    let other = (leader + 2) % cluster.len();
+    cluster.drop_messages(leader, other);
+    cluster.drop_messages(other, leader);
+    cluster.advance(config.election_timeout_min() * 2).await;
+    assert_ne!(cluster.node(leader).status().await.role, NodeRole::Leader);
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn followers_agree_on_the_leader() {
+    let cluster = TestCluster::new(3, config()).await.unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    // Followers learn of the leader from its first heartbeat
+    cluster.advance(config().heartbeat_interval()).await;
+
+    for i in (0..cluster.len()).filter(|&i| i != leader) {
+        let status = cluster.node(i).status().await;
+        assert_eq!(status.leader.as_deref(), Some(cluster.addr(leader)), "node {}", i);
+        assert_eq!(cluster.node(i).metrics().await.leader_id, Some(leader as u64));
+        match cluster.node(i).propose(occupy()).await {
+            Err(RaftError::NotLeader { leader: addr }) => {
+                assert_eq!(addr.as_deref(), Some(cluster.addr(leader)))
+            }
+            result => panic!("expected NotLeader, got {:?}", result),
+        }
+    }
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn unresponsive_peer_doesnt_hold_up_elections() {
+    let cluster = TestCluster::new(3, config()).await.unwrap();
+    let old = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    let term = cluster.node(old).status().await.current_term;
+
+    // Leader hangs, RPCs to it stay unanswered rather than failing
+    cluster.node(old).shutdown().await.unwrap();
+    cluster
+        .transport()
+        .register(cluster.addr(old).to_string(), Unresponsive);
+    let deadline = Instant::now() + TIMEOUT;
+    let leader = loop {
+        let leaders = cluster.leaders().await;
+        if let Some(&(i, _)) = leaders.iter().find(|&&(i, t)| i != old && t > term) {
+            break i;
+        }
+        assert!(Instant::now() < deadline, "no other node was elected");
+        cluster.advance(Duration::from_millis(10)).await;
+    };
+    // Leader commits with the one other node that replies
+    let propose = cluster.node(leader).propose(occupy());
+    time::timeout(TIMEOUT, propose).await.unwrap().unwrap();
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn heartbeats_are_handled_while_campaigning_against_a_stalled_peer() {
+    let transport = LocalTransport::new();
+    let node = idle_node(&transport, 0, 3).await;
+    for addr in &addrs(3)[1..] {
+        transport.register(addr.clone(), Unresponsive);
+    }
+
+    // Node campaigns, its vote requests hang until the RPC timeout
+    let request = TimeoutRequest { term: 0, id: 1 };
+    node.step(Message::TimeoutNow(request)).await.unwrap();
+    time::sleep(Duration::from_millis(1)).await;
+    let term = node.status().await.current_term;
+    assert!(term > 0);
+
+    let started = Instant::now();
+    assert!(append_entries(&node, term + 1, 1, (0, 0), vec![], 0).await.success);
+    assert!(started.elapsed() < config().rpc_timeout());
+    assert_eq!(node.status().await.leader.as_deref(), Some("node-1"));
+}
@@ -0,0 +1,104 @@
+#![cfg(feature = "testing")]
+
+mod common;
+
+use raft::{
+    raft::{RaftEvent, EVENT_CAPACITY},
+    testing::TestCluster,
+};
+use tokio::{
+    sync::broadcast::{error::RecvError, Receiver},
+    time::Duration,
+};
+
+use common::config;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Events received so far that are still kept for receiver.
+fn received(events: &mut Receiver<RaftEvent>) -> Vec<RaftEvent> {
+    let mut received = vec![];
+    while let Ok(event) = events.try_recv() {
+        received.push(event);
+    }
+    received
+}
+
+#[tokio::test(start_paused = true)]
+async fn events_follow_a_leader_change_and_a_commit() {
+    let cluster = TestCluster::new(3, config()).await.unwrap();
+    let mut events = vec![];
+    for i in 0..cluster.len() {
+        events.push(cluster.node(i).events().await);
+    }
+    let old = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    cluster.advance(Duration::from_secs(1)).await;
+    let term = cluster.node(old).status().await.current_term;
+    // However the first election went, it ends with every node knowing who leads
+    for (i, events) in events.iter_mut().enumerate() {
+        let event = received(events).pop();
+        let expected = match i == old {
+            true => RaftEvent::BecameLeader { term },
+            false => RaftEvent::BecameFollower {
+                leader: Some(cluster.addr(old).to_string()),
+            },
+        };
+        assert_eq!(event, Some(expected), "node {}", i);
+    }
+
+    let new = (old + 1) % cluster.len();
+    let other = (old + 2) % cluster.len();
+    cluster.elect(new, TIMEOUT).await.unwrap();
+    cluster.node(new).add_learner("node-3".to_string()).await.unwrap();
+    cluster.advance(Duration::from_secs(1)).await;
+
+    let led_by_new = RaftEvent::BecameFollower {
+        leader: Some(cluster.addr(new).to_string()),
+    };
+    let membership = cluster.node(new).membership().await;
+    assert!(membership.learners.contains(&"node-3".to_string()));
+    let committed = RaftEvent::MembershipChanged(membership);
+    // Others step down on the new leader's vote request, then learn of it from its heartbeat
+    let deposed = RaftEvent::BecameFollower { leader: None };
+    assert_eq!(
+        received(&mut events[new]),
+        [
+            RaftEvent::BecameCandidate { term: term + 1 },
+            RaftEvent::BecameLeader { term: term + 1 },
+            committed.clone(),
+        ]
+    );
+    for i in [old, other] {
+        let expected = [deposed.clone(), led_by_new.clone(), committed.clone()];
+        assert_eq!(received(&mut events[i]), expected, "node {}", i);
+    }
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn slow_receiver_lags_without_holding_up_the_node() {
+    let cluster = TestCluster::new(3, config()).await.unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    let node = cluster.node(leader);
+    let mut events = node.events().await;
+
+    // Every change commits while nothing reads the receiver
+    let changes = EVENT_CAPACITY + 10;
+    for i in 0..changes {
+        match i % 2 {
+            0 => node.add_learner("node-3".to_string()).await.unwrap(),
+            _ => node.remove_node("node-3".to_string()).await.unwrap(),
+        };
+    }
+    assert_eq!(
+        events.recv().await,
+        Err(RecvError::Lagged((changes - EVENT_CAPACITY) as u64))
+    );
+    // Receiver carries on from the oldest event kept
+    let kept = received(&mut events);
+    assert_eq!(kept.len(), EVENT_CAPACITY);
+    assert!(kept
+        .iter()
+        .all(|event| matches!(event, RaftEvent::MembershipChanged(_))));
+    cluster.shutdown().await.unwrap();
+}
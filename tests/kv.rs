@@ -0,0 +1,45 @@
+#![cfg(feature = "testing")]
+
+#[path = "../examples/kv/store.rs"]
+mod store;
+
+use raft::{config::Config, testing::TestCluster};
+use std::collections::HashMap;
+use tokio::time::Duration;
+
+use store::{KvCommand, KvStore};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[tokio::test(start_paused = true)]
+async fn key_value_store_replicates() {
+    let cluster = TestCluster::with_state_machine(3, Config::default(), |_| KvStore::default())
+        .await
+        .unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+
+    let commands = vec![
+        KvCommand::Set("language".to_string(), "rust".to_string()),
+        KvCommand::Set("consensus".to_string(), "raft".to_string()),
+        KvCommand::Delete("language".to_string()),
+    ];
+    for command in commands {
+        cluster.node(leader).propose(command).await.unwrap();
+    }
+    let entries = cluster
+        .node(leader)
+        .linearizable_read(|store| store.entries.clone())
+        .await
+        .unwrap();
+    let expected: HashMap<_, _> = vec![("consensus".to_string(), "raft".to_string())]
+        .into_iter()
+        .collect();
+    assert_eq!(entries, expected);
+
+    cluster.assert_state_machines_agree(TIMEOUT).await;
+    for i in 0..cluster.len() {
+        let store = cluster.node(i).state_machine();
+        assert_eq!(store.lock().await.entries, expected, "store of node {}", i);
+    }
+    cluster.shutdown().await.unwrap();
+}
@@ -0,0 +1,277 @@
+mod common;
+
+use raft::{
+    raft::{EntryKind, LogEntry, NodeRole, RaftDetails, Standing},
+    raft_proto::EntryReply,
+};
+
+use common::addrs;
+
+/// Follower at index 1 of a cluster of 3 whose log holds entries of the given terms.
+fn follower(terms: &[u64]) -> RaftDetails {
+    let mut details = RaftDetails::new(1, addrs(3));
+    details.log.append(&entries(1, terms)).unwrap();
+    details.current_term = terms.last().copied().unwrap_or_default();
+    details
+}
+
+/// Leader at index 0 of a cluster of 3, elected in term with a log holding entries of the given
+/// terms.
+fn leader(term: u64, terms: &[u64]) -> RaftDetails {
+    let mut details = RaftDetails::new(0, addrs(3)[1..].to_vec());
+    details.log.append(&entries(1, terms)).unwrap();
+    details.current_term = term;
+    details.become_leader();
+    details
+}
+
+/// Entries of the given terms, the first at index from.
+fn entries(from: u64, terms: &[u64]) -> Vec<LogEntry> {
+    terms
+        .iter()
+        .zip(from..)
+        .map(|(&term, index)| LogEntry {
+            index,
+            term,
+            kind: EntryKind::Normal,
+            data: vec![],
+        })
+        .collect()
+}
+
+/// Terms of the entries in the log of details.
+fn terms(details: &RaftDetails) -> Vec<u64> {
+    let entries = details.log.entries(1..details.last_log_index() + 1);
+    entries.iter().map(|entry| entry.term).collect()
+}
+
+/// Replicates the log of leader to follower node-1 until it matches, returning for each
+/// AppendEntries whether the follower accepted it and how many entries it carried.
+fn repair(leader: &mut RaftDetails, follower: &mut RaftDetails) -> Vec<(bool, usize)> {
+    let mut round_trips = vec![];
+    loop {
+        let request = leader.append_request("node-1");
+        let entries = request.entries.iter().cloned().map(LogEntry::from).collect();
+        let success = follower
+            .append_log(request.prev_log_index, request.prev_log_term, entries)
+            .unwrap();
+        let (conflict_index, conflict_term) = match success {
+            true => (0, 0),
+            false => follower.conflict(request.prev_log_index),
+        };
+        let reply = EntryReply {
+            term: follower.current_term,
+            success,
+            conflict_index,
+            conflict_term,
+            id: 1,
+        };
+        leader
+            .handle_append_reply("node-1", &request, &reply)
+            .unwrap();
+        round_trips.push((success, request.entries.len()));
+        if success && terms(follower) == terms(leader) {
+            return round_trips;
+        }
+        assert!(round_trips.len() < 1000, "logs never converged");
+    }
+}
+
+#[test]
+fn divergent_tail_is_replaced() {
+    let mut details = follower(&[1, 1, 2, 2, 2]);
+
+    // Leader of term 3 agrees upto index 2, after which it has entries of term 3
+    let appended = details.append_log(2, 1, entries(3, &[3, 3])).unwrap();
+    assert!(appended);
+    assert_eq!(terms(&details), [1, 1, 3, 3]);
+}
+
+#[test]
+fn entries_after_mismatching_prev_term_are_rejected() {
+    let mut details = follower(&[1, 1, 2]);
+
+    let appended = details.append_log(3, 3, entries(4, &[3])).unwrap();
+    assert!(!appended);
+    assert_eq!(terms(&details), [1, 1, 2]);
+    // Leader is pointed at the start of the conflicting term
+    assert_eq!(details.conflict(3), (3, 2));
+}
+
+#[test]
+fn entries_of_previous_terms_commit_only_with_one_of_the_current_term() {
+    // Entry 2 of term 2 is on a majority once node-1 stores it, but a leader of term 4 that never
+    // heard of it could still overwrite it
+    let mut details = leader(3, &[1, 2]);
+    details.match_index.insert("node-1".to_string(), 2);
+    details.advance_commit_index().unwrap();
+    assert_eq!(details.commit_index, 0);
+
+    let index = details.append_entry(EntryKind::Normal, vec![]).unwrap();
+    details.match_index.insert("node-1".to_string(), index);
+    details.advance_commit_index().unwrap();
+    assert_eq!(details.commit_index, 3);
+}
+
+#[test]
+fn removed_leader_counts_only_remaining_members() {
+    // Leader's own removal committed, node-1 and node-2 are left to agree on the rest
+    let mut details = leader(2, &[1, 2, 2]);
+    details.standing = Standing::Removed;
+    assert_eq!(details.quorum(), 2);
+
+    details.match_index.insert("node-1".to_string(), 3);
+    details.advance_commit_index().unwrap();
+    assert_eq!(details.commit_index, 0);
+    details.match_index.insert("node-2".to_string(), 3);
+    details.advance_commit_index().unwrap();
+    assert_eq!(details.commit_index, 3);
+}
+
+#[test]
+fn follower_far_behind_is_repaired_in_one_backtrack() {
+    let mut leader = leader(2, &[1; 110]);
+    let mut follower = follower(&[1; 10]);
+
+    let round_trips = repair(&mut leader, &mut follower);
+    assert_eq!(round_trips, [(false, 0), (true, 100)]);
+
+    // Reported conflicting term is skipped in one go, rather than an entry at a time
+    let mut leader = self::leader(3, &[vec![1; 10], vec![3; 100]].concat());
+    let mut follower = self::follower(&[vec![1; 10], vec![2; 100]].concat());
+
+    let round_trips = repair(&mut leader, &mut follower);
+    assert_eq!(round_trips, [(false, 0), (true, 100)]);
+}
+
+#[test]
+fn next_index_walks_back_on_rejection() {
+    let mut leader = leader(2, &[1; 5]);
+    assert_eq!(leader.next_index["node-1"], 6);
+
+    // Follower that gives no hint moves next index back one entry per rejection
+    for next_index in (4..6).rev() {
+        let request = leader.append_request("node-1");
+        let reply = EntryReply {
+            term: 2,
+            success: false,
+            id: 1,
+            ..EntryReply::default()
+        };
+        leader
+            .handle_append_reply("node-1", &request, &reply)
+            .unwrap();
+        assert_eq!(leader.next_index["node-1"], next_index);
+        assert_eq!(leader.match_index.get("node-1").copied().unwrap_or(0), 0);
+    }
+
+    let request = leader.append_request("node-1");
+    assert_eq!((request.prev_log_index, request.entries.len()), (3, 2));
+    let reply = EntryReply {
+        term: 2,
+        success: true,
+        id: 1,
+        ..EntryReply::default()
+    };
+    leader
+        .handle_append_reply("node-1", &request, &reply)
+        .unwrap();
+    assert_eq!(leader.match_index["node-1"], 5);
+    assert_eq!(leader.next_index["node-1"], 6);
+}
+
+#[test]
+fn follower_missing_10k_entries_converges_in_a_few_round_trips() {
+    let mut leader = leader(2, &[vec![1; 10], vec![2; 10_000]].concat());
+    let mut follower = follower(&[1; 10]);
+
+    let round_trips = repair(&mut leader, &mut follower);
+    let rejected = round_trips.iter().filter(|(success, _)| !success).count();
+    assert_eq!(rejected, 1);
+    // Entries catch up in as many rounds as the most entries sent at once takes
+    let chunks = 10_000 / leader.max_append_entries;
+    assert_eq!(round_trips.len(), 1 + chunks);
+}
+
+#[test]
+fn replies_commit_entries_of_previous_terms_only_with_one_of_the_current_term() {
+    let mut leader = leader(3, &[1, 2]);
+    let reply = EntryReply {
+        term: 3,
+        success: true,
+        id: 1,
+        ..EntryReply::default()
+    };
+
+    // Follower acknowledging entry 2 of term 2 makes a majority that commits nothing
+    let request = leader.append_request("node-1");
+    leader
+        .handle_append_reply("node-1", &request, &reply)
+        .unwrap();
+    assert_eq!(leader.match_index["node-1"], 2);
+    assert_eq!(leader.commit_index, 0);
+
+    leader.append_entry(EntryKind::Normal, vec![]).unwrap();
+    let request = leader.append_request("node-1");
+    leader
+        .handle_append_reply("node-1", &request, &reply)
+        .unwrap();
+    assert_eq!(leader.commit_index, 3);
+}
+
+#[test]
+fn followers_of_figure_7_converge_on_the_leaders_log() {
+    // Leader of term 8 appended an entry of its own term, as it does once elected
+    let leader_terms = [1, 1, 1, 4, 4, 5, 5, 6, 6, 6, 8];
+    let followers: [&[u64]; 6] = [
+        &[1, 1, 1, 4, 4, 5, 5, 6, 6],
+        &[1, 1, 1, 4],
+        &[1, 1, 1, 4, 4, 5, 5, 6, 6, 6, 6],
+        &[1, 1, 1, 4, 4, 5, 5, 6, 6, 6, 7, 7],
+        &[1, 1, 1, 4, 4, 4, 4],
+        &[1, 1, 1, 2, 2, 2, 3, 3, 3, 3, 3],
+    ];
+    for (follower_terms, name) in followers.iter().zip("abcdef".chars()) {
+        let mut leader = leader(8, &leader_terms);
+        let mut follower = follower(follower_terms);
+
+        let round_trips = repair(&mut leader, &mut follower);
+        assert_eq!(terms(&follower), leader_terms, "follower ({})", name);
+        assert!(round_trips.len() <= 4, "follower ({}) took {:?}", name, round_trips);
+    }
+}
+
+#[test]
+fn leader_learns_of_a_newer_term_from_a_heartbeat_reply() {
+    let mut leader = leader(2, &[1, 2]);
+    leader.voted_for = Some(0);
+
+    // Follower moved on to a term whose election this leader missed
+    let request = leader.append_request("node-1");
+    let reply = EntryReply {
+        term: 3,
+        success: false,
+        id: 1,
+        ..EntryReply::default()
+    };
+    leader
+        .handle_append_reply("node-1", &request, &reply)
+        .unwrap();
+    assert_eq!(leader.role(), NodeRole::Follower);
+    assert_eq!(leader.current_term, 3);
+    assert_eq!(leader.voted_for, None);
+}
+
+#[test]
+fn follower_10k_entries_behind_catches_up_in_bounded_chunks() {
+    let mut leader = leader(2, &[2; 10_000]);
+    leader.max_append_entries = 300;
+    let mut follower = follower(&[]);
+
+    let round_trips = repair(&mut leader, &mut follower);
+    assert!(round_trips.iter().all(|&(_, entries)| entries <= 300));
+    // First is rejected as the follower's log is empty, the rest each carry a chunk
+    assert_eq!(round_trips.len(), 1 + 10_000usize.div_ceil(300));
+    // Heartbeats to a follower that caught up carry no entries
+    assert!(leader.append_request("node-1").entries.is_empty());
+}
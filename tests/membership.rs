@@ -0,0 +1,185 @@
+#![cfg(feature = "testing")]
+
+mod common;
+
+use raft::{
+    config::Config, error::RaftError, node::RaftNode, state_machine::StateMachine,
+    testing::TestCluster, transport::LocalTransport,
+};
+use tokio::time::{sleep, Duration, Instant};
+
+use common::{addrs, applied, config, spawn_node, Recorder};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Voting members and learners node knows of, sorted.
+async fn membership<S: StateMachine>(node: &RaftNode<S>) -> (Vec<String>, Vec<String>) {
+    let mut membership = node.membership().await;
+    membership.members.sort();
+    membership.learners.sort();
+    (membership.members, membership.learners)
+}
+
+/// Waits for every one of nodes to know of members as the voting members of the cluster, with no
+/// learners, panics if they don't within timeout.
+async fn wait_for_members<S: StateMachine>(
+    nodes: &[&RaftNode<S>],
+    members: &[String],
+    timeout: Duration,
+) {
+    let deadline = Instant::now() + timeout;
+    for (i, node) in nodes.iter().enumerate() {
+        while membership(node).await != (members.to_vec(), vec![]) {
+            assert!(Instant::now() < deadline, "node {} has {:?}", i, membership(node).await);
+            sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn joined_node_is_known_to_all() {
+    let cluster = TestCluster::new(3, config()).await.unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    let node = spawn_node(cluster.transport(), 3, "node-3", addrs(3), config()).await;
+
+    node.join(cluster.addr(leader).to_string()).await.unwrap();
+    let nodes = [cluster.node(0), cluster.node(1), cluster.node(2), &node];
+    wait_for_members(&nodes, &addrs(4), TIMEOUT).await;
+    node.shutdown().await.unwrap();
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn cluster_commits_while_a_node_joins() {
+    let cluster = TestCluster::with_state_machine(3, config(), |_| Recorder::default())
+        .await
+        .unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    let node = spawn_node(cluster.transport(), 3, "node-3", addrs(3), config()).await;
+
+    let joining = node.clone();
+    let addr = cluster.addr(leader).to_string();
+    let join = tokio::spawn(async move { joining.join(addr).await });
+    // Proposals keep committing for as long as the node takes to join
+    let mut proposed = vec![];
+    for i in 0u32.. {
+        if join.is_finished() && proposed.len() >= 10 {
+            break;
+        }
+        let command = i.to_be_bytes().to_vec();
+        let propose = cluster.node(leader).propose(command.clone());
+        let index = tokio::time::timeout(TIMEOUT, propose).await.unwrap().unwrap();
+        proposed.push((index, command));
+    }
+    join.await.unwrap().unwrap();
+
+    let nodes = [cluster.node(0), cluster.node(1), cluster.node(2), &node];
+    wait_for_members(&nodes, &addrs(4), TIMEOUT).await;
+    let last = proposed.last().unwrap().0;
+    tokio::time::timeout(TIMEOUT, node.wait_applied(last)).await.unwrap();
+    assert_eq!(applied(&node).await, proposed);
+    node.shutdown().await.unwrap();
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn removing_a_member_shrinks_the_quorum() {
+    let cluster = TestCluster::with_state_machine(4, config(), |_| Recorder::default())
+        .await
+        .unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    let (removed, down) = ((leader + 1) % 4, (leader + 2) % 4);
+
+    cluster
+        .node(leader)
+        .remove_node(cluster.addr(removed).to_string())
+        .await
+        .unwrap();
+    let members: Vec<_> = (0..4).filter(|&i| i != removed).collect();
+    let nodes: Vec<_> = members.iter().map(|&i| cluster.node(i)).collect();
+    let mut addrs: Vec<_> = members.iter().map(|&i| cluster.addr(i).to_string()).collect();
+    addrs.sort();
+    wait_for_members(&nodes, &addrs, TIMEOUT).await;
+    cluster.partition(removed);
+
+    // Leader and one other member are a majority of three, though not of the four before
+    cluster.partition(down);
+    let propose = cluster.node(leader).propose(vec![1]);
+    let index = tokio::time::timeout(TIMEOUT, propose).await.unwrap().unwrap();
+    assert!(cluster.node(leader).status().await.commit_index >= index);
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn learner_is_promoted_once_caught_up() {
+    let config = Config {
+        auto_promote: false,
+        promotion_lag: 0,
+        ..config()
+    };
+    let cluster = TestCluster::with_state_machine(3, config.clone(), |_| Recorder::default())
+        .await
+        .unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    let node: RaftNode<Recorder> =
+        spawn_node(cluster.transport(), 3, "node-3", addrs(3), config.clone()).await;
+    cluster.transport().disconnect("node-3");
+
+    // Learner that can't be reached holds up neither its own addition nor later commits
+    let leader = cluster.node(leader);
+    let added = tokio::time::timeout(TIMEOUT, leader.add_learner("node-3".to_string())).await;
+    added.unwrap().unwrap();
+    let index = leader.propose(vec![1]).await.unwrap();
+    assert!(matches!(
+        leader.promote("node-3".to_string()).await,
+        Err(RaftError::LearnerBehind)
+    ));
+
+    cluster.transport().reconnect("node-3");
+    tokio::time::timeout(TIMEOUT, node.wait_applied(index))
+        .await
+        .unwrap();
+    // Leader learns of its progress with the next heartbeat
+    cluster.advance(config.heartbeat_interval() * 2).await;
+    leader.promote("node-3".to_string()).await.unwrap();
+    let nodes = [cluster.node(0), cluster.node(1), cluster.node(2), &node];
+    wait_for_members(&nodes, &addrs(4), TIMEOUT).await;
+    node.shutdown().await.unwrap();
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn bootstrapped_node_grows_into_a_cluster() {
+    let transport = LocalTransport::new();
+    let bootstrap = Config {
+        bootstrap: true,
+        ..config()
+    };
+    let seed: RaftNode<Recorder> = spawn_node(&transport, 0, "node-0", vec![], bootstrap).await;
+    // Seed leads alone as soon as it committed its configuration
+    seed.wait_applied(1).await;
+    let first = tokio::time::timeout(TIMEOUT, seed.propose(vec![1])).await;
+    let first = first.unwrap().unwrap();
+    assert!(seed.status().await.commit_index >= first);
+
+    let mut nodes = vec![seed];
+    for i in 1..3 {
+        let addr = format!("node-{}", i);
+        let node = spawn_node(&transport, i, &addr, addrs(1), config()).await;
+        node.join("node-0".to_string()).await.unwrap();
+        nodes.push(node);
+    }
+    let all: Vec<_> = nodes.iter().collect();
+    wait_for_members(&all, &addrs(3), TIMEOUT).await;
+
+    let second = nodes[0].propose(vec![2]).await.unwrap();
+    for node in &nodes {
+        tokio::time::timeout(TIMEOUT, node.wait_applied(second))
+            .await
+            .unwrap();
+        assert_eq!(applied(node).await, [(first, vec![1]), (second, vec![2])]);
+    }
+    for node in &nodes {
+        node.shutdown().await.unwrap();
+    }
+}
@@ -0,0 +1,67 @@
+#![cfg(feature = "testing")]
+
+mod common;
+
+use raft::{config::Config, error::RaftError, testing::TestCluster};
+use tokio::time::Duration;
+
+use common::{applied, config, Recorder};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[tokio::test(start_paused = true)]
+async fn read_index_reflects_writes_committed_before_it() {
+    let cluster = TestCluster::with_state_machine(3, config(), |_| Recorder::default())
+        .await
+        .unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    let written = cluster.node(leader).propose(vec![1]).await.unwrap();
+
+    // Proposal in flight while reading may or may not be seen, the one committed before must be
+    let proposer = cluster.node(leader).clone();
+    let proposal = tokio::spawn(async move { proposer.propose(vec![2]).await });
+    let node = cluster.node(leader);
+    let index = node.read_index().await.unwrap();
+    assert!(index >= written);
+    assert!(node.status().await.last_applied >= index);
+    let read = node.linearizable_read(|recorder| recorder.applied.clone());
+    assert!(read.await.unwrap().contains(&(written, vec![1])));
+
+    // Reads issued once the proposal committed see it too
+    let concurrent = proposal.await.unwrap().unwrap();
+    assert!(node.read_index().await.unwrap() >= concurrent);
+    assert!(applied(node).await.contains(&(concurrent, vec![2])));
+
+    let follower = (leader + 1) % cluster.len();
+    assert!(matches!(
+        cluster.node(follower).read_index().await,
+        Err(RaftError::NotLeader { .. })
+    ));
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn lease_reads_need_no_rpcs() {
+    let config = Config {
+        lease_reads: true,
+        ..config()
+    };
+    let cluster = TestCluster::with_state_machine(3, config.clone(), |_| Recorder::default())
+        .await
+        .unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    let written = cluster.node(leader).propose(vec![1]).await.unwrap();
+    // Heartbeats a majority acknowledged extend the lease
+    cluster.advance(config.heartbeat_interval()).await;
+
+    // Leader can't reach anyone, so any RPC a read needed would fail
+    for i in (0..cluster.len()).filter(|&i| i != leader) {
+        cluster.drop_messages(leader, i);
+    }
+    let node = cluster.node(leader);
+    let heartbeats = node.metrics().await.heartbeats_sent;
+    let read = node.read_local(|recorder| recorder.applied.clone()).await;
+    assert!(read.unwrap().contains(&(written, vec![1])));
+    assert_eq!(node.metrics().await.heartbeats_sent, heartbeats);
+    cluster.shutdown().await.unwrap();
+}
@@ -0,0 +1,256 @@
+#![cfg(feature = "testing")]
+
+mod common;
+
+use raft::{
+    config::Config,
+    node::RaftNode,
+    raft_proto::{
+        raft_server::Raft, Byte, EntryReply, EntryRequest, Null, ProposeReply, SnapshotReply,
+        SnapshotRequest, TimeoutReply, TimeoutRequest, VoteReply, VoteRequest,
+    },
+    testing::TestCluster,
+};
+use tokio::time::{self, Duration, Instant};
+use tonic::{Request, Response, Status};
+
+use common::{applied, config, Recorder};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Node that handles every RPC only once delay passed since it arrived.
+struct Delayed {
+    node: RaftNode<Recorder>,
+    delay: Duration,
+}
+
+#[tonic::async_trait]
+impl Raft for Delayed {
+    async fn request_vote(
+        &self,
+        request: Request<VoteRequest>,
+    ) -> Result<Response<VoteReply>, Status> {
+        time::sleep(self.delay).await;
+        self.node.request_vote(request).await
+    }
+
+    async fn append_entries(
+        &self,
+        request: Request<EntryRequest>,
+    ) -> Result<Response<EntryReply>, Status> {
+        time::sleep(self.delay).await;
+        self.node.append_entries(request).await
+    }
+
+    async fn install_snapshot(
+        &self,
+        request: Request<SnapshotRequest>,
+    ) -> Result<Response<SnapshotReply>, Status> {
+        time::sleep(self.delay).await;
+        self.node.install_snapshot(request).await
+    }
+
+    async fn timeout_now(
+        &self,
+        request: Request<TimeoutRequest>,
+    ) -> Result<Response<TimeoutReply>, Status> {
+        time::sleep(self.delay).await;
+        self.node.timeout_now(request).await
+    }
+
+    async fn join(&self, request: Request<Byte>) -> Result<Response<Null>, Status> {
+        time::sleep(self.delay).await;
+        Raft::join(&self.node, request).await
+    }
+
+    async fn leave(&self, request: Request<Byte>) -> Result<Response<Null>, Status> {
+        time::sleep(self.delay).await;
+        Raft::leave(&self.node, request).await
+    }
+
+    async fn propose(&self, request: Request<Byte>) -> Result<Response<ProposeReply>, Status> {
+        time::sleep(self.delay).await;
+        Raft::propose(&self.node, request).await
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn proposals_reach_every_log() {
+    let cluster = TestCluster::with_state_machine(3, config(), |_| Recorder::default())
+        .await
+        .unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+
+    let mut proposed = vec![];
+    for command in [b"a", b"b", b"c"] {
+        let index = cluster.node(leader).propose(command.to_vec()).await.unwrap();
+        proposed.push((index, command.to_vec()));
+    }
+    cluster.assert_state_machines_agree(TIMEOUT).await;
+    for i in 0..cluster.len() {
+        assert_eq!(applied(cluster.node(i)).await, proposed, "log of node {}", i);
+    }
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn committed_entries_apply_once_in_order() {
+    let cluster = TestCluster::with_state_machine(3, config(), |_| Recorder::default())
+        .await
+        .unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+
+    let mut proposed = vec![];
+    for i in 0..3u8 {
+        let index = cluster.node(leader).propose(vec![i]).await.unwrap();
+        proposed.push((index, vec![i]));
+    }
+    // Heartbeats carrying the same commit index again apply nothing more
+    cluster.advance(Duration::from_secs(1)).await;
+    cluster.assert_state_machines_agree(TIMEOUT).await;
+    for i in 0..cluster.len() {
+        assert_eq!(applied(cluster.node(i)).await, proposed, "node {}", i);
+    }
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn committed_entries_survive_leader_partition() {
+    let cluster = TestCluster::with_state_machine(3, config(), |_| Recorder::default())
+        .await
+        .unwrap();
+    let old = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    let first = cluster.node(old).propose(vec![1]).await.unwrap();
+
+    cluster.partition(old);
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    assert_ne!(leader, old);
+    let term = cluster.node(leader).status().await.current_term;
+    let second = cluster.node(leader).propose(vec![2]).await.unwrap();
+
+    cluster.heal(old);
+    cluster.advance(Duration::from_secs(1)).await;
+    assert_eq!(cluster.assert_one_leader_in_term(term).await, leader);
+    cluster.assert_state_machines_agree(TIMEOUT).await;
+    for i in 0..cluster.len() {
+        let applied = applied(cluster.node(i)).await;
+        assert_eq!(applied, [(first, vec![1]), (second, vec![2])], "node {}", i);
+    }
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn slow_follower_doesnt_delay_commits() {
+    let cluster = TestCluster::with_state_machine(3, config(), |_| Recorder::default())
+        .await
+        .unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    let slow = (leader + 1) % cluster.len();
+    let delayed = Delayed {
+        node: cluster.node(slow).clone(),
+        delay: Duration::from_secs(1),
+    };
+    cluster
+        .transport()
+        .register(cluster.addr(slow).to_string(), delayed);
+
+    // Leader and the other follower are a majority, whose round trip is all a commit waits on,
+    // rather than the slow follower's or the RPC timeout cutting it short
+    let started = Instant::now();
+    cluster.node(leader).propose(vec![1]).await.unwrap();
+    assert!(started.elapsed() < config().rpc_timeout() / 10);
+    cluster.shutdown().await.unwrap();
+}
+
+/// Number of AppendEntries the leader of a cluster running with config sends to commit count
+/// proposals made at once.
+async fn append_entries_sent(config: Config, count: u32) -> u64 {
+    let cluster = TestCluster::with_state_machine(3, config, |_| Recorder::default())
+        .await
+        .unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    let node = cluster.node(leader);
+    let before = node.metrics().await.heartbeats_sent;
+
+    let proposals: Vec<_> = (0..count)
+        .map(|i| {
+            let node = node.clone();
+            tokio::spawn(async move { node.propose(i.to_be_bytes().to_vec()).await })
+        })
+        .collect();
+    for proposal in proposals {
+        proposal.await.unwrap().unwrap();
+    }
+    let sent = node.metrics().await.heartbeats_sent - before;
+    cluster.shutdown().await.unwrap();
+    sent
+}
+
+#[tokio::test(start_paused = true)]
+async fn batching_replicates_proposals_in_fewer_rpcs() {
+    let unbatched = Config {
+        batch_linger: 0,
+        ..config()
+    };
+    let batched = Config {
+        batch_linger: 5,
+        max_batch_entries: 64,
+        ..config()
+    };
+    // Each AppendEntries carries a batch where it would otherwise carry a single proposal, so
+    // as many more proposals commit per round trip
+    let unbatched = append_entries_sent(unbatched, 256).await;
+    let batched = append_entries_sent(batched, 256).await;
+    assert!(batched * 16 <= unbatched, "{} batched against {}", batched, unbatched);
+}
+
+/// Proposes commands to whichever node leads, returning those that committed along with their
+/// indices. Proposals may fail as leadership moves, those that do are left out.
+async fn propose_all(
+    cluster: &TestCluster<Recorder>,
+    commands: Vec<Vec<u8>>,
+) -> Vec<(u64, Vec<u8>)> {
+    let mut committed = vec![];
+    for command in commands {
+        let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+        let propose = cluster.node(leader).propose(command.clone());
+        if let Ok(Ok(index)) = time::timeout(TIMEOUT, propose).await {
+            committed.push((index, command));
+        }
+    }
+    committed
+}
+
+#[tokio::test(start_paused = true)]
+async fn lossy_network_loses_no_committed_entry() {
+    let cluster = TestCluster::with_state_machine(3, config(), |_| Recorder::default())
+        .await
+        .unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    let transport = cluster.transport();
+    transport.set_drop_rate(0.1);
+    transport.set_delay(Duration::from_millis(1), Duration::from_millis(20));
+
+    let commands = |range: std::ops::Range<u8>| range.map(|i| vec![i]).collect();
+    let mut committed = propose_all(&cluster, commands(0..20)).await;
+    cluster.partition(leader);
+    committed.extend(propose_all(&cluster, commands(20..40)).await);
+    cluster.heal(leader);
+    committed.extend(propose_all(&cluster, commands(40..60)).await);
+
+    transport.set_drop_rate(0.0);
+    transport.set_delay(Duration::from_secs(0), Duration::from_secs(0));
+    cluster.advance(Duration::from_secs(1)).await;
+    let leaders = cluster.leaders().await;
+    let mut terms: Vec<_> = leaders.iter().map(|(_, term)| term).collect();
+    terms.sort();
+    terms.dedup();
+    assert_eq!(terms.len(), leaders.len(), "two leaders in a term: {:?}", leaders);
+    cluster.assert_state_machines_agree(TIMEOUT).await;
+    let applied = applied(cluster.node(0)).await;
+    assert!(!committed.is_empty());
+    for entry in &committed {
+        assert!(applied.contains(entry), "committed {:?} was lost", entry);
+    }
+    cluster.shutdown().await.unwrap();
+}
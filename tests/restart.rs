@@ -0,0 +1,80 @@
+#![cfg(feature = "testing")]
+
+mod common;
+
+use raft::{
+    config::Config,
+    node::{RaftNode, RaftNodeBuilder},
+    transport::LocalTransport,
+};
+use std::{fs, path::Path};
+
+use common::{addrs, append_entries, applied, entry, temp_dir, Recorder};
+
+/// Node of a single node cluster persisting to dir, not started yet.
+fn single_node(dir: &Path) -> RaftNodeBuilder<Recorder> {
+    RaftNode::builder(0, "node-0".to_string(), vec![])
+        .state_machine(Recorder::default())
+        .transport(LocalTransport::new().bind("node-0"))
+        .path(dir.to_path_buf())
+}
+
+/// Copies the files a node persisted to from into to, as they would be found after a crash.
+fn crash(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for file in fs::read_dir(from).unwrap() {
+        let file = file.unwrap();
+        fs::copy(file.path(), to.join(file.file_name())).unwrap();
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn restarted_follower_recovers_log_and_commit_index() {
+    let dir = temp_dir("recover-log");
+    let start = || {
+        RaftNode::builder(1, "node-1".to_string(), addrs(3))
+            .transport(LocalTransport::new().bind("node-1"))
+            .path(dir.clone())
+            .start()
+    };
+    let node = start().await.unwrap();
+    let entries = vec![entry(1, 1), entry(2, 1), entry(3, 2)];
+    assert!(append_entries(&node, 2, 0, (0, 0), entries, 0).await.success);
+    assert!(append_entries(&node, 2, 0, (3, 2), vec![], 2).await.success);
+    drop(node);
+
+    let node = start().await.unwrap();
+    let (status, metrics) = (node.status().await, node.metrics().await);
+    assert_eq!(status.commit_index, 2);
+    assert_eq!(metrics.log_length, 3);
+    // Log still ends with the entry of term 2 at index 3, and holds those before it
+    assert!(append_entries(&node, 2, 0, (3, 2), vec![], 2).await.success);
+    assert!(append_entries(&node, 2, 0, (1, 1), vec![], 2).await.success);
+}
+
+#[tokio::test(start_paused = true)]
+async fn crashed_node_replays_committed_entries() {
+    let (dir, crashed) = (temp_dir("replay"), temp_dir("replay-crashed"));
+    let mut node = single_node(&dir).start().await.unwrap();
+    let mut runner = node.clone();
+    tokio::spawn(async move {
+        let config = Config {
+            bootstrap: true,
+            ..Config::default()
+        };
+        let _ = runner.run(config).await;
+    });
+    node.wait_applied(1).await;
+
+    let mut last = 0;
+    for i in 0..3u8 {
+        last = node.propose(vec![i]).await.unwrap();
+    }
+    node.wait_applied(last).await;
+    let before = applied(&node).await;
+    crash(&dir, &crashed);
+
+    node = single_node(&crashed).start().await.unwrap();
+    assert_eq!(node.status().await.commit_index, last);
+    assert_eq!(applied(&node).await, before);
+}
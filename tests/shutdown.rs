@@ -0,0 +1,55 @@
+use raft::{
+    config::Config,
+    error::RaftError,
+    node::RaftNode,
+    raft_proto::{raft_client::RaftClient, VoteRequest},
+    state_machine::{RaftCommand, RaftTask},
+};
+use tokio::time::{self, Duration};
+
+#[tokio::test]
+async fn shut_down_node_refuses_connections() {
+    let node = RaftNode::start(0, "127.0.0.1:0".to_string(), vec![])
+        .await
+        .unwrap();
+    let url = format!("http://{}", node.local_addr().unwrap());
+    let request = VoteRequest {
+        term: 1,
+        candidate_id: 1,
+        candidate_addr: "127.0.0.1:1".to_string(),
+        ..VoteRequest::default()
+    };
+    let mut client = RaftClient::connect(url.clone()).await.unwrap();
+    client.request_vote(request).await.unwrap();
+
+    node.shutdown().await.unwrap();
+    assert!(RaftClient::connect(url).await.is_err());
+    let command = RaftCommand {
+        task: RaftTask::Occupy,
+        node: 0,
+        data: vec![],
+    };
+    assert!(matches!(
+        node.propose(command).await,
+        Err(RaftError::ShuttingDown)
+    ));
+}
+
+#[tokio::test]
+async fn run_returns_once_shut_down() {
+    let node = RaftNode::start(0, "127.0.0.1:0".to_string(), vec![])
+        .await
+        .unwrap();
+    let mut runner = node.clone();
+    let config = Config {
+        bootstrap: true,
+        ..Config::default()
+    };
+    let run = tokio::spawn(async move { runner.run(config).await });
+    // Node has gone through startup and leads its cluster once it applied its configuration
+    node.wait_applied(1).await;
+
+    node.shutdown().await.unwrap();
+    let result = time::timeout(Duration::from_secs(5), run).await;
+    assert!(matches!(result, Ok(Ok(Ok(())))));
+}
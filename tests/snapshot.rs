@@ -0,0 +1,141 @@
+#![cfg(feature = "testing")]
+
+mod common;
+
+use raft::{
+    config::Config,
+    node::{Message, RaftNode},
+    raft::RaftEvent,
+    raft_proto::SnapshotRequest,
+    state_machine::StateMachine,
+    testing::TestCluster,
+    transport::LocalTransport,
+};
+use std::fs;
+use tokio::time::Duration;
+
+use common::{addrs, append_entries, applied, entry, temp_dir, Recorder};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[tokio::test(start_paused = true)]
+async fn lagging_follower_installs_snapshot_in_chunks() {
+    let config = Config {
+        snapshot_threshold: 5,
+        snapshot_chunk_size: 16,
+        ..Config::default()
+    };
+    let cluster = TestCluster::with_state_machine(3, config, |_| Recorder::default())
+        .await
+        .unwrap();
+    let leader = cluster.wait_for_leader(TIMEOUT).await.unwrap();
+    let follower = (leader + 1) % cluster.len();
+    cluster.partition(follower);
+
+    for i in 0..20u8 {
+        cluster.node(leader).propose(vec![i]).await.unwrap();
+    }
+    // Leader compacted entries the follower is missing
+    cluster.advance(Duration::from_secs(1)).await;
+    let snapshot_index = cluster.node(leader).metrics().await.snapshot_index;
+    assert!(snapshot_index > 0);
+
+    let mut events = cluster.node(follower).events().await;
+    cluster.heal(follower);
+    cluster.assert_state_machines_agree(TIMEOUT).await;
+    let installed = loop {
+        match events.recv().await.unwrap() {
+            RaftEvent::SnapshotInstalled { index, .. } => break index,
+            _ => continue,
+        }
+    };
+    assert!(installed >= snapshot_index);
+    let commands = applied(cluster.node(follower)).await;
+    assert_eq!(commands.len(), 20);
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn installed_snapshot_replaces_covered_log() {
+    let node = RaftNode::builder(1, "node-1".to_string(), addrs(3))
+        .state_machine(Recorder::default())
+        .transport(LocalTransport::new().bind("node-1"))
+        .start()
+        .await
+        .unwrap();
+    let entries = vec![entry(1, 1), entry(2, 1), entry(3, 1)];
+    assert!(append_entries(&node, 1, 0, (0, 0), entries, 0).await.success);
+
+    let snapshot = Recorder {
+        applied: vec![(4, b"x".to_vec())],
+    };
+    let request = SnapshotRequest {
+        term: 2,
+        id: 0,
+        last_included_index: 5,
+        last_included_term: 2,
+        offset: 0,
+        data: snapshot.snapshot(),
+        done: true,
+        leader_addr: "node-0".to_string(),
+        membership: vec![],
+    };
+    node.step(Message::InstallSnapshot(request)).await.unwrap();
+    let (status, metrics) = (node.status().await, node.metrics().await);
+    assert_eq!((status.commit_index, status.last_applied), (5, 5));
+    assert_eq!((metrics.snapshot_index, metrics.log_length), (5, 0));
+    assert_eq!(applied(&node).await, snapshot.applied);
+    // Log carries on from the entry the snapshot replaced last
+    assert!(append_entries(&node, 2, 0, (5, 2), vec![entry(6, 2)], 6).await.success);
+}
+
+#[tokio::test(start_paused = true)]
+async fn log_file_shrinks_once_compacted() {
+    let dir = temp_dir("compacted-log");
+    let mut node = RaftNode::builder(0, "node-0".to_string(), vec![])
+        .state_machine(Recorder::default())
+        .transport(LocalTransport::new().bind("node-0"))
+        .path(dir.clone())
+        .start()
+        .await
+        .unwrap();
+    let mut runner = node.clone();
+    tokio::spawn(async move {
+        let config = Config {
+            bootstrap: true,
+            snapshot_threshold: 10,
+            ..Config::default()
+        };
+        let _ = runner.run(config).await;
+    });
+    node.wait_applied(1).await;
+    let log_size = || fs::metadata(dir.join("log")).unwrap().len();
+
+    // Bootstrap configuration and these make up 9 entries, one short of the threshold
+    let mut last = 0;
+    for _ in 0..8 {
+        last = node.propose(vec![0; 1024]).await.unwrap();
+    }
+    node.wait_applied(last).await;
+    let uncompacted = log_size();
+    assert!(uncompacted > 8 * 1024);
+
+    last = node.propose(vec![0; 1024]).await.unwrap();
+    node.wait_applied(last).await;
+    while node.metrics().await.snapshot_index < last {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(log_size() < 1024);
+    let before = applied(&node).await;
+    node.shutdown().await.unwrap();
+
+    // Restarting node restores the snapshot in place of the entries it replaced
+    node = RaftNode::builder(0, "node-0".to_string(), vec![])
+        .state_machine(Recorder::default())
+        .transport(LocalTransport::new().bind("node-0"))
+        .path(dir)
+        .start()
+        .await
+        .unwrap();
+    assert_eq!(applied(&node).await, before);
+}
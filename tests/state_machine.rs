@@ -0,0 +1,36 @@
+use raft::{
+    raft::RaftData,
+    state_machine::{RaftCommand, RaftStateMachine, StateMachine},
+};
+use std::panic;
+
+#[test]
+fn bad_snapshot_is_refused() {
+    let mut state_machine = RaftStateMachine::new();
+    // Node 5 is free, node 7 executes a task of 2 bytes
+    let snapshot = [&[1, 5, 7][..], &2u64.to_be_bytes(), &[1, 2]].concat();
+    state_machine.restore(&snapshot).unwrap();
+    assert_eq!(state_machine.snapshot(), snapshot);
+
+    for bad in [
+        &[3, 1][..],
+        &[0, 7, 0, 0],
+        &snapshot[..snapshot.len() - 1],
+        &[&[0, 7][..], &u64::MAX.to_be_bytes()].concat(),
+    ] {
+        assert!(state_machine.restore(bad).is_err(), "restored {:?}", bad);
+        assert_eq!(state_machine.snapshot(), snapshot);
+    }
+}
+
+#[test]
+fn commands_that_dont_decode_fail_try_from_bytes() {
+    assert!(<RaftCommand as RaftData>::try_from_bytes(vec![]).is_err());
+    let command = <RaftCommand as RaftData>::try_from_bytes(vec![0x85, 1, 2]).unwrap();
+    assert_eq!((command.node, command.data), (5, vec![1, 2]));
+
+    // from_bytes only gives up on what try_from_bytes couldn't decode
+    let decoded = panic::catch_unwind(|| <RaftCommand as RaftData>::from_bytes(vec![]));
+    assert!(decoded.is_err());
+    assert_eq!(Vec::<u8>::from_bytes(vec![1, 2]), [1, 2]);
+}
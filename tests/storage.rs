@@ -0,0 +1,204 @@
+mod common;
+
+use raft::{
+    error::RaftError,
+    node::{Message, RaftNode},
+    raft::{LogEntry, RaftDetails},
+    raft_proto::{self, VoteRequest},
+    storage::{
+        FileLog, HardState, LogStore, MemStorage, Membership, Snapshot, SnapshotMeta, Storage,
+    },
+    transport::LocalTransport,
+};
+use std::{
+    convert::TryFrom,
+    fs::{self, OpenOptions},
+    io,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tonic::Code;
+
+use common::{addrs, entry, request_vote, temp_dir};
+
+#[test]
+fn hard_state_survives_restart() {
+    let dir = temp_dir("hard-state");
+    let mut details = RaftDetails::recover(0, addrs(3), dir.clone()).unwrap();
+    details.current_term = 3;
+    details.voted_for = Some(2);
+    details.persist().unwrap();
+    drop(details);
+
+    let details = RaftDetails::recover(0, addrs(3), dir).unwrap();
+    assert_eq!(details.current_term, 3);
+    assert_eq!(details.voted_for, Some(2));
+}
+
+/// Storage in memory that fails every save while it is told to.
+struct FailingStorage {
+    storage: MemStorage,
+    failing: Arc<AtomicBool>,
+}
+
+impl Storage for FailingStorage {
+    fn load(&mut self) -> io::Result<HardState> {
+        self.storage.load()
+    }
+
+    fn save(&mut self, hard_state: &HardState) -> io::Result<()> {
+        if self.failing.load(Ordering::SeqCst) {
+            return Err(io::Error::other("disk full"));
+        }
+        self.storage.save(hard_state)
+    }
+
+    fn load_snapshot(&mut self) -> io::Result<Option<Snapshot>> {
+        self.storage.load_snapshot()
+    }
+
+    fn save_snapshot(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        self.storage.save_snapshot(snapshot)
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn vote_isnt_granted_unless_persisted() {
+    let failing = Arc::new(AtomicBool::new(true));
+    let storage = FailingStorage {
+        storage: MemStorage::new(),
+        failing: failing.clone(),
+    };
+    let node = RaftNode::builder(0, "node-0".to_string(), addrs(3))
+        .storage(storage)
+        .transport(LocalTransport::new().bind("node-0"))
+        .start()
+        .await
+        .unwrap();
+    let request = VoteRequest {
+        term: 1,
+        candidate_id: 1,
+        last_log_index: 0,
+        last_log_term: 0,
+        candidate_addr: "node-1".to_string(),
+        pre_vote: false,
+    };
+
+    let reply = node.step(Message::RequestVote(request)).await;
+    assert_eq!(reply.unwrap_err().code(), Code::Internal);
+    failing.store(false, Ordering::SeqCst);
+    assert!(request_vote(&node, 1, 1, 0, 0).await.grant);
+}
+
+/// Writes a log to path holding 3 entries of term 1, each carrying 8 bytes of data, returning
+/// the size of its file.
+fn written_log(path: &Path) -> u64 {
+    let mut log = FileLog::open(path.to_path_buf(), 0).unwrap();
+    let entries: Vec<_> = (1..=3).map(|index| entry(index, 1).into()).collect();
+    log.append(&entries).unwrap();
+    fs::metadata(path).unwrap().len()
+}
+
+#[test]
+fn torn_last_entry_is_dropped_on_open() {
+    let path = temp_dir("torn-log").join("log");
+    let len = written_log(&path);
+    // Crash while the last entry was written left only some of it
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(len - 3).unwrap();
+    drop(file);
+
+    let mut log = FileLog::open(path.clone(), 0).unwrap();
+    assert_eq!(log.last_index(), 2);
+    assert_eq!(log.get(2).unwrap().data, 2u64.to_be_bytes());
+    log.append(&[LogEntry::from(entry(3, 2))]).unwrap();
+    drop(log);
+
+    let log = FileLog::open(path, 0).unwrap();
+    let terms: Vec<_> = log.entries(1..4).iter().map(|entry| entry.term).collect();
+    assert_eq!(terms, [1, 1, 2]);
+}
+
+#[test]
+fn corrupt_entry_before_the_last_fails_open() {
+    let path = temp_dir("corrupt-log").join("log");
+    let len = written_log(&path);
+    // Each entry takes up 29 bytes, the first holds its data from 17 bytes in
+    let mut bytes = fs::read(&path).unwrap();
+    let first = len as usize - 3 * 29;
+    bytes[first + 20] ^= 0xff;
+    fs::write(&path, bytes).unwrap();
+
+    let error = FileLog::open(path, 0).err().unwrap();
+    assert!(matches!(
+        RaftError::from(error),
+        RaftError::LogCorruption { index: 1 }
+    ));
+}
+
+#[test]
+fn corrupt_length_of_an_entry_before_the_last_fails_open() {
+    let path = temp_dir("corrupt-length").join("log");
+    let len = written_log(&path);
+    let bytes = fs::read(&path).unwrap();
+    let first = len as usize - 3 * 29;
+
+    // Length taken for more than is left of the file mustn't drop the entries that follow
+    for at in first + 9..first + 17 {
+        let mut bytes = bytes.clone();
+        bytes[at] ^= 0xff;
+        fs::write(&path, bytes).unwrap();
+        let error = FileLog::open(path.clone(), 0).err().unwrap();
+        assert!(
+            matches!(RaftError::from(error), RaftError::LogCorruption { index: 1 }),
+            "flipped byte {} of the length",
+            at - first - 9
+        );
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+    }
+}
+
+#[test]
+fn torn_committed_entry_fails_open() {
+    let path = temp_dir("torn-committed").join("log");
+    let len = written_log(&path);
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[len as usize - 1] ^= 0xff;
+    fs::write(&path, bytes).unwrap();
+
+    // Last entry is garbled, but hard state says it committed
+    let error = FileLog::open(path.clone(), 3).err().unwrap();
+    assert!(matches!(
+        RaftError::from(error),
+        RaftError::LogCorruption { index: 3 }
+    ));
+    assert_eq!(fs::metadata(&path).unwrap().len(), len);
+    assert_eq!(FileLog::open(path, 2).unwrap().last_index(), 2);
+}
+
+#[test]
+fn snapshot_meta_converts_to_proto_and_back() {
+    let meta = SnapshotMeta {
+        index: 7,
+        term: 2,
+        membership: Some(Membership::new(addrs(3))),
+    };
+    let proto = raft_proto::SnapshotMeta::from(meta.clone());
+    assert_eq!((proto.index, proto.term), (7, 2));
+    assert_eq!(SnapshotMeta::try_from(proto).unwrap(), meta);
+
+    // Empty membership stands for the one the node started with
+    let meta = SnapshotMeta::default();
+    let proto = raft_proto::SnapshotMeta::from(meta.clone());
+    assert!(proto.membership.is_empty());
+    assert_eq!(SnapshotMeta::try_from(proto).unwrap(), meta);
+
+    let proto = raft_proto::SnapshotMeta {
+        membership: vec![0xff],
+        ..raft_proto::SnapshotMeta::default()
+    };
+    assert!(SnapshotMeta::try_from(proto).is_err());
+}
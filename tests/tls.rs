@@ -0,0 +1,158 @@
+#![cfg(feature = "tls")]
+
+mod common;
+
+use raft::{
+    config::{Config, TlsConfig},
+    node::RaftNode,
+    raft::NodeRole,
+    raft_proto::{raft_client::RaftClient, VoteReply, VoteRequest},
+};
+use std::{fs, net::TcpListener, path::PathBuf};
+use tokio::time::{self, Duration, Instant};
+use tonic::{
+    transport::{Certificate, Channel, ClientTlsConfig, Identity},
+    Code, Status,
+};
+
+use common::{applied, Recorder};
+
+/// Path of file under `tests/tls`, holding certificates that the test CA, or for the intruder a
+/// rogue one, signed for `raft.local` and 127.0.0.1.
+fn fixture(file: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/tls")
+        .join(file)
+}
+
+/// Certificates of name, trusting those the test CA signed.
+fn tls(name: &str) -> TlsConfig {
+    TlsConfig {
+        cert: fixture(&format!("{}.pem", name)),
+        key: fixture(&format!("{}.key", name)),
+        ca: fixture("ca.pem"),
+        domain: "raft.local".to_string(),
+        verify_peers: false,
+    }
+}
+
+/// Addresses on localhost at ports free when asked for.
+fn free_addrs(count: usize) -> Vec<String> {
+    let listeners: Vec<_> = (0..count)
+        .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+        .collect();
+    listeners
+        .iter()
+        .map(|listener| listener.local_addr().unwrap().to_string())
+        .collect()
+}
+
+/// Asks the node at addr for its vote as the candidate at candidate, presenting the certificate of
+/// name and trusting the test CA.
+async fn request_vote(addr: &str, name: &str, candidate: &str) -> Result<VoteReply, Status> {
+    let read = |file: String| fs::read(fixture(&file)).unwrap();
+    let identity = Identity::from_pem(read(format!("{}.pem", name)), read(format!("{}.key", name)));
+    let config = ClientTlsConfig::new()
+        .domain_name("raft.local")
+        .ca_certificate(Certificate::from_pem(read("ca.pem".to_string())))
+        .identity(identity);
+    let channel = Channel::from_shared(format!("https://{}", addr))
+        .unwrap()
+        .tls_config(config)
+        .unwrap();
+    let request = VoteRequest {
+        term: 1,
+        candidate_id: 1,
+        candidate_addr: candidate.to_string(),
+        ..VoteRequest::default()
+    };
+    // Handshake fails either while connecting or on the first request
+    let channel = channel
+        .connect()
+        .await
+        .map_err(|e| Status::unavailable(e.to_string()))?;
+    let reply = RaftClient::new(channel).request_vote(request).await?;
+    Ok(reply.into_inner())
+}
+
+#[tokio::test]
+async fn nodes_replicate_over_tls() {
+    let addrs = free_addrs(2);
+    let mut nodes = vec![];
+    for (i, addr) in addrs.iter().enumerate() {
+        let node = RaftNode::builder(i as u64, addr.clone(), addrs.clone())
+            .state_machine(Recorder::default())
+            .tls(tls(&format!("node{}", i + 1)))
+            .start()
+            .await
+            .unwrap();
+        let mut runner = node.clone();
+        tokio::spawn(async move {
+            let _ = runner.run(Config::default()).await;
+        });
+        nodes.push(node);
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let leader = loop {
+        let mut leader = None;
+        for (i, node) in nodes.iter().enumerate() {
+            if node.status().await.role == NodeRole::Leader {
+                leader = Some(i);
+            }
+        }
+        match leader {
+            Some(leader) => break leader,
+            None => assert!(Instant::now() < deadline, "no node was elected"),
+        }
+        time::sleep(Duration::from_millis(10)).await;
+    };
+    let follower = &nodes[1 - leader];
+    let index = nodes[leader].propose(vec![1]).await.unwrap();
+    time::timeout(Duration::from_secs(10), follower.wait_applied(index))
+        .await
+        .unwrap();
+    assert_eq!(applied(follower).await, [(index, vec![1])]);
+    assert_eq!(follower.metrics().await.leader_id, Some(leader as u64));
+    for node in &nodes {
+        node.shutdown().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn client_with_untrusted_certificate_is_refused() {
+    let addr = free_addrs(1).remove(0);
+    let node = RaftNode::builder(0, addr.clone(), vec![])
+        .tls(tls("node1"))
+        .start()
+        .await
+        .unwrap();
+
+    assert!(request_vote(&addr, "intruder", "127.0.0.1:1").await.is_err());
+    assert_eq!(node.status().await.current_term, 0);
+    assert!(request_vote(&addr, "node2", "127.0.0.1:1").await.is_ok());
+    assert_eq!(node.status().await.current_term, 1);
+    node.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn verified_peers_must_be_named_by_their_certificate() {
+    let addr = free_addrs(1).remove(0);
+    let tls = TlsConfig {
+        verify_peers: true,
+        ..tls("node1")
+    };
+    let node = RaftNode::builder(0, addr.clone(), vec![])
+        .tls(tls)
+        .start()
+        .await
+        .unwrap();
+
+    // Certificate of node2 the CA signed names node2 and raft.local, but not this address
+    let reply = request_vote(&addr, "node2", "127.0.0.1:1").await;
+    assert_eq!(reply.unwrap_err().code(), Code::Unauthenticated);
+    assert_eq!(node.status().await.current_term, 0);
+    assert!(request_vote(&addr, "node2", "node2").await.unwrap().grant);
+    assert_eq!(node.status().await.current_term, 1);
+    node.shutdown().await.unwrap();
+}
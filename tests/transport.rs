@@ -0,0 +1,141 @@
+use raft::{
+    node::RaftNode,
+    raft_proto::{EntryReply, EntryRequest},
+    transport::{GrpcTransport, RaftTransport},
+};
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::{
+    io,
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+use tonic::{Code, Request, Status};
+
+/// Forwards connections made to its address on to a node, counting those it forwarded. While
+/// down it closes connections as soon as they are made.
+struct Proxy {
+    addr: String,
+    dials: Arc<AtomicUsize>,
+    up: Arc<AtomicBool>,
+    connections: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl Proxy {
+    async fn new(node: SocketAddr) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = Proxy {
+            addr: listener.local_addr().unwrap().to_string(),
+            dials: Arc::default(),
+            up: Arc::new(AtomicBool::new(true)),
+            connections: Arc::default(),
+        };
+        let (dials, up, connections) = (
+            proxy.dials.clone(),
+            proxy.up.clone(),
+            proxy.connections.clone(),
+        );
+        tokio::spawn(async move {
+            while let Ok((mut inbound, _)) = listener.accept().await {
+                if !up.load(Ordering::SeqCst) {
+                    continue;
+                }
+                dials.fetch_add(1, Ordering::SeqCst);
+                let connection = tokio::spawn(async move {
+                    let mut outbound = TcpStream::connect(node).await.unwrap();
+                    let _ = io::copy_bidirectional(&mut inbound, &mut outbound).await;
+                });
+                connections.lock().unwrap().push(connection);
+            }
+        });
+        proxy
+    }
+
+    fn dials(&self) -> usize {
+        self.dials.load(Ordering::SeqCst)
+    }
+
+    /// Cuts every connection forwarded so far and refuses new ones until brought back up.
+    fn down(&self) {
+        self.up.store(false, Ordering::SeqCst);
+        for connection in self.connections.lock().unwrap().drain(..) {
+            connection.abort();
+        }
+    }
+
+    fn up(&self) {
+        self.up.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Node serving gRPC on a free port, that isn't run so it only answers RPCs.
+async fn node(id: u64) -> RaftNode {
+    RaftNode::start(id, "127.0.0.1:0".to_string(), vec![])
+        .await
+        .unwrap()
+}
+
+/// Sends a heartbeat of the leader of term 1 to the node at addr.
+async fn heartbeat(transport: &GrpcTransport, addr: &str) -> Result<EntryReply, Status> {
+    let request = EntryRequest {
+        term: 1,
+        id: 9,
+        leader_addr: "127.0.0.1:9".to_string(),
+        ..EntryRequest::default()
+    };
+    let reply = transport.append_entries(addr, Request::new(request)).await?;
+    Ok(reply.into_inner())
+}
+
+#[tokio::test]
+async fn heartbeats_reuse_one_connection_per_peer() {
+    let nodes = [node(0).await, node(1).await];
+    let mut proxies = vec![];
+    for node in nodes.iter() {
+        proxies.push(Proxy::new(node.local_addr().unwrap()).await);
+    }
+
+    // Clones share the connections, as the tasks of a node sending RPCs do
+    let transport = GrpcTransport::new();
+    for _ in 0..100 {
+        let transport = transport.clone();
+        for proxy in proxies.iter() {
+            heartbeat(&transport, &proxy.addr).await.unwrap();
+        }
+    }
+    for proxy in proxies.iter() {
+        assert_eq!(proxy.dials(), 1, "dials through {}", proxy.addr);
+    }
+}
+
+#[tokio::test]
+async fn failed_connection_is_redialed() {
+    let node = node(0).await;
+    let proxy = Proxy::new(node.local_addr().unwrap()).await;
+    let transport = GrpcTransport::new();
+    heartbeat(&transport, &proxy.addr).await.unwrap();
+    assert!(transport.is_connected(&proxy.addr));
+
+    proxy.down();
+    let status = heartbeat(&transport, &proxy.addr).await.unwrap_err();
+    assert!(
+        matches!(status.code(), Code::Unavailable | Code::Unknown),
+        "{:?}",
+        status
+    );
+    assert_eq!(proxy.dials(), 1);
+    assert!(!transport.is_connected(&proxy.addr));
+
+    // Connection that failed was forgotten, the next heartbeat dials a new one and keeps it
+    proxy.up();
+    for _ in 0..20 {
+        heartbeat(&transport, &proxy.addr).await.unwrap();
+        assert!(transport.is_connected(&proxy.addr));
+    }
+    assert_eq!(proxy.dials(), 2);
+}